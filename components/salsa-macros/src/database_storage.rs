@@ -124,6 +124,7 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
 
     //
     let mut for_each_ops = proc_macro2::TokenStream::new();
+    let mut dependency_graph_ops = proc_macro2::TokenStream::new();
     for (QueryGroup { group_path }, group_storage) in
         query_groups.iter().zip(&query_group_storage_names)
     {
@@ -132,6 +133,11 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
                 <Self as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(self);
             storage.for_each_query(self, &mut op);
         });
+        dependency_graph_ops.extend(quote! {
+            let storage: &#group_storage =
+                <Self as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(self);
+            storage.dependency_graph(self, graph);
+        });
     }
     output.extend(quote! {
         impl salsa::plumbing::DatabaseOps for #database_name {
@@ -141,6 +147,10 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
             ) {
                 #for_each_ops
             }
+
+            fn fill_dependency_graph(&self, graph: &mut salsa::debug::DependencyGraph<Self>) {
+                #dependency_graph_ops
+            }
         }
     });
 
@@ -172,6 +182,13 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
 
     output.extend(has_group_impls);
 
+    // Whether these impls are needed depends on the features of the
+    // `salsa` crate, not of this one, so let `salsa` decide.
+    let persistence = persistence(query_groups, &query_group_key_names);
+    output.extend(quote! {
+        salsa::__salsa_if_serde! { #persistence }
+    });
+
     if std::env::var("SALSA_DUMP").is_ok() {
         println!("~~~ database_storage");
         println!("{}", output.to_string());
@@ -181,6 +198,94 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
     output.into()
 }
 
+/// Emits the impls needed to save and load the database with salsa's
+/// `serde` feature. As in the `query_group` macro, they are generic
+/// over the database so that their bounds may be unsatisfiable, in
+/// which case the database simply cannot be saved.
+fn persistence(
+    query_groups: &PunctuatedQueryGroups,
+    query_group_key_names: &[proc_macro2::TokenStream],
+) -> proc_macro2::TokenStream {
+    let mut storage_bounds = proc_macro2::TokenStream::new();
+    let mut key_bounds = proc_macro2::TokenStream::new();
+    let mut for_each_ops = proc_macro2::TokenStream::new();
+    let mut encode_arms = proc_macro2::TokenStream::new();
+    let mut decode_arms = proc_macro2::TokenStream::new();
+    for (i, (query_group, group_key)) in query_groups.iter().zip(query_group_key_names).enumerate()
+    {
+        let group_path = &query_group.group_path;
+        let group_name = query_group.name();
+        let tag = proc_macro2::Literal::u32_suffixed(i as u32);
+        storage_bounds.extend(quote! {
+            #group_path: salsa::plumbing::QueryGroup<DB__>,
+            DB__: salsa::plumbing::HasQueryGroup<#group_path>,
+            <#group_path as salsa::plumbing::QueryGroup<DB__>>::GroupStorage:
+                salsa::plumbing::PersistentQueryGroup<DB__>,
+        });
+        key_bounds.extend(quote! {
+            #group_key: salsa::plumbing::PersistentDatabaseKey<DB__>,
+        });
+        for_each_ops.extend(quote! {
+            let storage = <DB__ as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(db);
+            salsa::plumbing::PersistentQueryGroup::for_each_persistent_query(storage, op);
+        });
+        encode_arms.extend(quote! {
+            __SalsaDatabaseKeyKind::#group_name(group_key) => salsa::plumbing::encode_tagged(
+                #tag,
+                salsa::plumbing::PersistentDatabaseKey::<DB__>::encode(group_key),
+            ),
+        });
+        decode_arms.extend(quote! {
+            #tag => __SalsaDatabaseKeyKind::#group_name(
+                salsa::plumbing::PersistentDatabaseKey::<DB__>::decode(bytes)?,
+            ),
+        });
+    }
+
+    quote! {
+        impl<DB__> salsa::plumbing::PersistentDatabaseOps<DB__> for __SalsaDatabaseStorage
+        where
+            DB__: salsa::Database,
+            #storage_bounds
+        {
+            fn for_each_persistent_query(
+                db: &DB__,
+                op: &mut dyn FnMut(
+                    &'static str,
+                    &dyn salsa::plumbing::PersistentQueryStorageOps<DB__>,
+                ),
+            ) {
+                #for_each_ops
+            }
+        }
+
+        impl<DB__> salsa::plumbing::PersistentDatabaseKey<DB__> for __SalsaDatabaseKey
+        where
+            DB__: salsa::Database,
+            #key_bounds
+        {
+            fn encode(&self) -> std::io::Result<Vec<u8>> {
+                match &self.kind {
+                    #encode_arms
+                }
+            }
+
+            fn decode(bytes: &[u8]) -> std::io::Result<Self> {
+                let (tag, bytes) = salsa::plumbing::decode_tag(bytes)?;
+                let kind = match tag {
+                    #decode_arms
+                    _ => {
+                        return Err(salsa::plumbing::invalid_data(
+                            "unknown query group in database key",
+                        ))
+                    }
+                };
+                Ok(__SalsaDatabaseKey { kind })
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct QueryGroupList {
     query_groups: PunctuatedQueryGroups,
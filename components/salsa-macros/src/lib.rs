@@ -49,14 +49,46 @@ mod query_group;
 /// - Storage attributes: control how the query data is stored and set. These
 ///   are described in detail in the section below.
 ///   - `#[salsa::input]`
+///   - `#[salsa::interned]`
 ///   - `#[salsa::memoized]`
 ///   - `#[salsa::volatile]`
 ///   - `#[salsa::dependencies]`
+///   - `#[salsa::hashed]`
 /// - Query execution:
 ///   - `#[salsa::invoke(path::to::my_fn)]` -- for a non-input, this
 ///     indicates the function to call when a query must be
 ///     recomputed. The default is to call a function in the same
 ///     module with the same name as the query.
+///   - `#[salsa::cycle(path::to::my_recover_fn)]` -- for a non-input,
+///     indicates the function to call when the query takes part in a
+///     cycle. It is invoked with the database, the cycle (as
+///     `&salsa::Cycle<DB>`, whose `participants` are the database-keys
+///     of the queries that take part in it) and the query's keys, and
+///     returns the value to use in place of the cycle. The default is
+///     to panic.
+///   - `#[salsa::eq_with(path::to::my_eq_fn)]` -- for a memoized
+///     query, indicates the function used to tell whether a recomputed
+///     value changed, in place of `Eq`. It is invoked with references
+///     to the old and new values, and returns true if they are equal.
+///   - `#[salsa::no_eq]` -- for a memoized query, considers a
+///     recomputed value to always have changed; the value need not
+///     implement `Eq`.
+///   - `#[salsa::memoize_if(path::to::my_memoize_fn)]` -- for a
+///     memoized query, indicates the function that decides whether to
+///     memoize the value for some keys. It is invoked with references
+///     to the query's keys, and returns true to memoize the value; the
+///     values of other keys are not memoized, as with
+///     `#[salsa::dependencies]`. The default is to memoize all values.
+///   - `#[salsa::lru(capacity)]` -- for a non-input, retains only
+///     the memoized values of the `capacity` most recently used keys;
+///     the values of other keys are discarded (but their dependencies
+///     are kept) and recomputed when next needed. The capacity can be
+///     changed at runtime with `QueryTable::set_lru_capacity`. The
+///     default is to retain all values.
+///   - `#[salsa::heap_size]` -- includes the heap memory owned by the
+///     keys and values of the query, which must implement
+///     `salsa::HeapSize`, in `Database::memory_report`. The default is
+///     to count only their `size_of`.
 ///   - `#[query_type(MyQueryTypeName)]` specifies the name of the
 ///     dummy struct created fo the query. Default is the name of the
 ///     query, in camel case, plus the word "Query" (e.g.,
@@ -78,6 +110,22 @@ mod query_group;
 /// value has changed, and so we will potentially re-execute derived
 /// queries that read (transitively) from this input.
 ///
+/// Values can also be removed again with `remove_my_query(key)`. To
+/// observe whether a value is set without panicking, use
+/// `my_query_if_set(key)`, which returns an `Option`; derived queries
+/// that read an absent value are re-executed once it is set.
+///
+/// ## Interned queries
+///
+/// Specifying `#[salsa::interned]` will give you an **interned
+/// query**. Each distinct key given to an interned query is assigned
+/// a fresh id, and giving the same key again returns the same id. The
+/// value type must implement `salsa::InternKey` (`salsa::InternId`
+/// itself does), so it is typically a small `Copy` newtype that is
+/// cheap to hash and compare. For an interned query `intern_foo`, a
+/// `lookup_intern_foo` query is generated as well, which maps an id
+/// back to the key it was created for.
+///
 /// ## Derived queries
 ///
 /// Derived queries are specified by a function.
@@ -87,13 +135,33 @@ mod query_group;
 ///   the value, but then compare against the old memoized value,
 ///   which can significantly reduce the amount of recomputation
 ///   required in new revisions. This does require that the value
-///   implements `Eq`.
+///   implements `Eq` (unless `#[salsa::eq_with]` or `#[salsa::no_eq]`
+///   is set).
 /// - `#[salsa::volatile]` -- indicates that the inputs are not fully
 ///   captured by salsa. The result will be recomputed once per revision.
 /// - `#[salsa::dependencies]` -- does not cache the value, so it will
 ///   be recomputed every time it is needed. We do track the inputs, however,
 ///   so if they have not changed, then things that rely on this query
 ///   may be known not to have changed.
+/// - `#[salsa::hashed]` -- like `#[salsa::dependencies]`, but also stores
+///   a 64-bit hash of the value. If the inputs have changed, we will
+///   recompute the value and compare its hash against the old one, so
+///   things that rely on this query are recomputed only if the value
+///   (most likely) changed. This does require that the value implements
+///   `Hash`.
+///
+/// ## Cycles
+///
+/// If a derived query (transitively) invokes itself, salsa reports a
+/// cycle. By default this panics. If the query that is re-entered has a
+/// `#[salsa::cycle(recover_fn)]` attribute, then `recover_fn` is invoked
+/// instead and the value it returns is used as the result of the
+/// recursive invocation. Otherwise, if another query participating in
+/// the cycle has a recovery function, the computation unwinds to the
+/// innermost such query, which uses the value of its recovery function
+/// as its result. Once the outer invocations complete, every query
+/// participating in the cycle that has a recovery function uses the
+/// value of its recovery function as its result.
 ///
 /// ## Attribute combinations
 ///
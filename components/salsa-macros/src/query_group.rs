@@ -3,7 +3,7 @@ use heck::CamelCase;
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::ToTokens;
-use syn::{parse_macro_input, FnArg, Ident, ItemTrait, ReturnType, TraitItem};
+use syn::{parse_macro_input, parse_quote, FnArg, Ident, ItemTrait, ReturnType, TraitItem};
 
 /// Implementation for `[salsa::query_group]` decorator.
 pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream {
@@ -23,6 +23,12 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
             TraitItem::Method(method) => {
                 let mut storage = QueryStorage::Memoized;
                 let mut invoke = None;
+                let mut cycle = None;
+                let mut eq_with = None;
+                let mut no_eq = false;
+                let mut memoize_if = None;
+                let mut lru = None;
+                let mut heap_size = false;
                 let mut query_type = Ident::new(
                     &format!("{}Query", method.sig.ident.to_string().to_camel_case()),
                     Span::call_site(),
@@ -56,13 +62,40 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                             storage = QueryStorage::Dependencies;
                             num_storages += 1;
                         }
+                        "hashed" => {
+                            storage = QueryStorage::Hashed;
+                            num_storages += 1;
+                        }
                         "input" => {
                             storage = QueryStorage::Input;
                             num_storages += 1;
                         }
+                        "interned" => {
+                            storage = QueryStorage::Interned;
+                            num_storages += 1;
+                        }
                         "invoke" => {
                             invoke = Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
                         }
+                        "cycle" => {
+                            cycle = Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                        }
+                        "eq_with" => {
+                            eq_with = Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                        }
+                        "no_eq" => {
+                            no_eq = true;
+                        }
+                        "memoize_if" => {
+                            memoize_if =
+                                Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                        }
+                        "lru" => {
+                            lru = Some(parse_macro_input!(tts as Parenthesized<syn::LitInt>).0);
+                        }
+                        "heap_size" => {
+                            heap_size = true;
+                        }
                         "query_type" => {
                             query_type = parse_macro_input!(tts as Parenthesized<Ident>).0;
                         }
@@ -74,8 +107,50 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 if num_storages > 1 {
                     panic!("multiple storage attributes specified");
                 }
-                if invoke.is_some() && storage == QueryStorage::Input {
-                    panic!("#[salsa::invoke] cannot be set on #[salsa::input] queries");
+                if eq_with.is_some() && no_eq {
+                    panic!("#[salsa::eq_with] and #[salsa::no_eq] cannot both be set");
+                }
+                if storage != QueryStorage::Memoized {
+                    let storage_name = storage.attribute_name();
+                    if eq_with.is_some() {
+                        panic!(
+                            "#[salsa::eq_with] cannot be set on #[salsa::{}] queries",
+                            storage_name
+                        );
+                    }
+                    if no_eq {
+                        panic!(
+                            "#[salsa::no_eq] cannot be set on #[salsa::{}] queries",
+                            storage_name
+                        );
+                    }
+                    if memoize_if.is_some() {
+                        panic!(
+                            "#[salsa::memoize_if] cannot be set on #[salsa::{}] queries",
+                            storage_name
+                        );
+                    }
+                }
+                if !storage.is_derived() {
+                    let storage_name = storage.attribute_name();
+                    if invoke.is_some() {
+                        panic!(
+                            "#[salsa::invoke] cannot be set on #[salsa::{}] queries",
+                            storage_name
+                        );
+                    }
+                    if cycle.is_some() {
+                        panic!(
+                            "#[salsa::cycle] cannot be set on #[salsa::{}] queries",
+                            storage_name
+                        );
+                    }
+                    if lru.is_some() {
+                        panic!(
+                            "#[salsa::lru] cannot be set on #[salsa::{}] queries",
+                            storage_name
+                        );
+                    }
                 }
 
                 // Extract keys.
@@ -106,6 +181,44 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                     ),
                 };
 
+                // For interned queries, we need `lookup_foo`, which maps
+                // the interned value back to the key.
+                let lookup_query = if let QueryStorage::Interned = storage {
+                    let lookup_fn_name = Ident::new(
+                        &format!("lookup_{}", method.sig.ident),
+                        method.sig.ident.span(),
+                    );
+                    let lookup_keys = vec![value.clone()];
+                    let lookup_value: syn::Type = if keys.len() == 1 {
+                        keys[0].clone()
+                    } else {
+                        let keys = &keys;
+                        parse_quote!((#(#keys),*))
+                    };
+                    Some(Query {
+                        query_type: Ident::new(
+                            &format!("{}Query", lookup_fn_name.to_string().to_camel_case()),
+                            Span::call_site(),
+                        ),
+                        fn_name: lookup_fn_name,
+                        attrs: vec![],
+                        storage: QueryStorage::InternedLookup {
+                            intern_query_type: query_type.clone(),
+                        },
+                        keys: lookup_keys,
+                        value: lookup_value,
+                        invoke: None,
+                        cycle: None,
+                        eq_with: None,
+                        no_eq: false,
+                        memoize_if: None,
+                        lru: None,
+                        heap_size: false,
+                    })
+                } else {
+                    None
+                };
+
                 queries.push(Query {
                     query_type,
                     fn_name: method.sig.ident.clone(),
@@ -114,7 +227,14 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                     keys,
                     value,
                     invoke,
+                    cycle,
+                    eq_with,
+                    no_eq,
+                    memoize_if,
+                    lru,
+                    heap_size,
                 });
+                queries.extend(lookup_query);
             }
             _ => (),
         }
@@ -136,6 +256,7 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
     let mut query_descriptor_maybe_change = proc_macro2::TokenStream::new();
     let mut storage_fields = proc_macro2::TokenStream::new();
     let mut storage_defaults = proc_macro2::TokenStream::new();
+    let mut storage_types = vec![];
     for query in &queries {
         let key_names: &Vec<_> = &(0..query.keys.len())
             .map(|i| Ident::new(&format!("key{}", i), Span::call_site()))
@@ -160,8 +281,12 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
         // For input queries, we need `set_foo` etc
         if let QueryStorage::Input = query.storage {
             let set_fn_name = Ident::new(&format!("set_{}", fn_name), fn_name.span());
+            let set_with_durability_fn_name =
+                Ident::new(&format!("set_{}_with_durability", fn_name), fn_name.span());
             let set_constant_fn_name =
                 Ident::new(&format!("set_constant_{}", fn_name), fn_name.span());
+            let remove_fn_name = Ident::new(&format!("remove_{}", fn_name), fn_name.span());
+            let if_set_fn_name = Ident::new(&format!("{}_if_set", fn_name), fn_name.span());
 
             query_fn_declarations.extend(quote! {
                 /// Set the value of the `#fn_name` input.
@@ -173,6 +298,17 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 /// those queries have been cancelled.
                 fn #set_fn_name(&mut self, #(#key_names: #keys,)* value__: #value);
 
+                /// Set the value of the `#fn_name` input with a
+                /// specific durability instead of the default of
+                /// `Durability::Low`.
+                ///
+                /// See [`#fn_name()`][] for details.
+                ///
+                /// *Note:* Setting values will trigger cancellation
+                /// of any ongoing queries; this method blocks until
+                /// those queries have been cancelled.
+                fn #set_with_durability_fn_name(&mut self, #(#key_names: #keys,)* value__: #value, durability__: salsa::Durability);
+
                 /// Set the value of the `#fn_name` input and promise
                 /// that its value will never change again.
                 ///
@@ -182,6 +318,21 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 /// of any ongoing queries; this method blocks until
                 /// those queries have been cancelled.
                 fn #set_constant_fn_name(&mut self, #(#key_names: #keys,)* value__: #value);
+
+                /// Remove the value of the `#fn_name` input, if any.
+                ///
+                /// See [`#fn_name()`][] for details.
+                ///
+                /// *Note:* Removing values will trigger cancellation
+                /// of any ongoing queries; this method blocks until
+                /// those queries have been cancelled.
+                fn #remove_fn_name(&mut self, #(#key_names: #keys),*);
+
+                /// Get the value of the `#fn_name` input, or `None` if
+                /// it was never set or has been removed.
+                ///
+                /// See [`#fn_name()`][] for details.
+                fn #if_set_fn_name(&self, #(#key_names: #keys),*) -> Option<#value>;
             });
 
             query_fn_definitions.extend(quote! {
@@ -189,9 +340,21 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                     <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).set((#(#key_names),*), value__)
                 }
 
+                fn #set_with_durability_fn_name(&mut self, #(#key_names: #keys,)* value__: #value, durability__: salsa::Durability) {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).set_with_durability((#(#key_names),*), value__, durability__)
+                }
+
                 fn #set_constant_fn_name(&mut self, #(#key_names: #keys,)* value__: #value) {
                     <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).set_constant((#(#key_names),*), value__)
                 }
+
+                fn #remove_fn_name(&mut self, #(#key_names: #keys),*) {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).remove((#(#key_names),*))
+                }
+
+                fn #if_set_fn_name(&self, #(#key_names: #keys),*) -> Option<#value> {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table(self).get_if_set((#(#key_names),*))
+                }
             });
         }
 
@@ -219,9 +382,20 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
         // A field for the storage struct
         //
         // FIXME(#120): the pub should not be necessary once we complete the transition
+        //
+        // The lookup query of an interned query only implements
+        // `Query` for databases that have the query group, so we name
+        // its storage type directly.
+        let storage_type = match &query.storage {
+            QueryStorage::InternedLookup { intern_query_type } => quote! {
+                salsa::plumbing::LookupInternedStorage<DB__, #qt, #intern_query_type>
+            },
+            _ => quote! { <#qt as salsa::Query<DB__>>::Storage },
+        };
         storage_fields.extend(quote! {
-            pub #fn_name: <#qt as salsa::Query<DB__>>::Storage,
+            pub #fn_name: #storage_type,
         });
+        storage_types.push(storage_type);
         storage_defaults.extend(quote! { #fn_name: Default::default(), });
     }
 
@@ -270,17 +444,43 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
     for query in &queries {
         let fn_name = &query.fn_name;
         let qt = &query.query_type;
-        let storage = Ident::new(
-            match query.storage {
-                QueryStorage::Memoized => "MemoizedStorage",
-                QueryStorage::Volatile => "VolatileStorage",
-                QueryStorage::Dependencies => "DependencyStorage",
-                QueryStorage::Input => "InputStorage",
-            },
-            Span::call_site(),
-        );
+        let storage = match &query.storage {
+            QueryStorage::Memoized if query.eq_with.is_some() || query.no_eq => {
+                quote!(salsa::plumbing::CustomEqStorage<DB, Self>)
+            }
+            QueryStorage::Memoized => quote!(salsa::plumbing::MemoizedStorage<DB, Self>),
+            QueryStorage::Volatile => quote!(salsa::plumbing::VolatileStorage<DB, Self>),
+            QueryStorage::Dependencies => quote!(salsa::plumbing::DependencyStorage<DB, Self>),
+            QueryStorage::Hashed => quote!(salsa::plumbing::HashedStorage<DB, Self>),
+            QueryStorage::Input => quote!(salsa::plumbing::InputStorage<DB, Self>),
+            QueryStorage::Interned => quote!(salsa::plumbing::InternedStorage<DB, Self>),
+            QueryStorage::InternedLookup { intern_query_type } => {
+                quote!(salsa::plumbing::LookupInternedStorage<DB, Self, #intern_query_type>)
+            }
+        };
+        // The lookup storage reaches into the storage of the interned
+        // query, so it needs access to the group storage.
+        let storage_bounds = match &query.storage {
+            QueryStorage::InternedLookup { .. } => {
+                quote!(DB: salsa::plumbing::HasQueryGroup<#group_struct>,)
+            }
+            _ => proc_macro2::TokenStream::new(),
+        };
         let keys = &query.keys;
         let value = &query.value;
+        let heap_size = if query.heap_size {
+            quote! {
+                fn key_heap_size(key: &Self::Key) -> usize {
+                    salsa::HeapSize::heap_size(key)
+                }
+
+                fn value_heap_size(value: &Self::Value) -> usize {
+                    salsa::HeapSize::heap_size(value)
+                }
+            }
+        } else {
+            proc_macro2::TokenStream::new()
+        };
 
         // Emit the query struct and implement the Query trait on it.
         output.extend(quote! {
@@ -291,10 +491,11 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
             where
                 DB: #trait_name,
                 DB: salsa::Database,
+                #storage_bounds
             {
                 type Key = (#(#keys),*);
                 type Value = #value;
-                type Storage = salsa::plumbing::#storage<DB, Self>;
+                type Storage = #storage;
                 type Group = #group_struct;
                 type GroupStorage = #group_storage<DB>;
                 type GroupKey = #group_key;
@@ -306,11 +507,13 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 fn group_key(key: Self::Key) -> Self::GroupKey {
                     #group_key::#fn_name(key)
                 }
+
+                #heap_size
             }
         });
 
         // Implement the QueryFunction trait for all queries except inputs.
-        if query.storage != QueryStorage::Input {
+        if query.storage.is_derived() {
             let span = query.fn_name.span();
             let key_names: &Vec<_> = &(0..query.keys.len())
                 .map(|i| Ident::new(&format!("key{}", i), Span::call_site()))
@@ -324,16 +527,63 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 Some(i) => i.into_token_stream(),
                 None => query.fn_name.clone().into_token_stream(),
             };
+            let recover = match &query.cycle {
+                Some(cycle_recovery_fn) => quote! {
+                    const CYCLE_RECOVERY: bool = true;
+
+                    fn recover(
+                        db: &DB,
+                        cycle: &salsa::Cycle<DB>,
+                        #key_pattern: &<Self as salsa::Query<DB>>::Key,
+                    ) -> Option<<Self as salsa::Query<DB>>::Value> {
+                        Some(#cycle_recovery_fn(db, cycle, #(#key_names.clone()),*))
+                    }
+                },
+                None => proc_macro2::TokenStream::new(),
+            };
+            let value_eq = match &query.eq_with {
+                Some(eq_fn) => quote! {
+                    fn value_eq(
+                        old_value: &<Self as salsa::Query<DB>>::Value,
+                        new_value: &<Self as salsa::Query<DB>>::Value,
+                    ) -> bool {
+                        #eq_fn(old_value, new_value)
+                    }
+                },
+                None => proc_macro2::TokenStream::new(),
+            };
+            let memoize_value = match &query.memoize_if {
+                Some(memoize_if_fn) => quote! {
+                    fn memoize_value(#key_pattern: &<Self as salsa::Query<DB>>::Key) -> bool {
+                        #memoize_if_fn(#(#key_names),*)
+                    }
+                },
+                None => proc_macro2::TokenStream::new(),
+            };
+            let lru = match &query.lru {
+                Some(capacity) => quote! {
+                    const LRU_CAPACITY: usize = #capacity;
+                },
+                None => proc_macro2::TokenStream::new(),
+            };
             output.extend(quote_spanned! {span=>
                 impl<DB> salsa::plumbing::QueryFunction<DB> for #qt
                 where
                     DB: #trait_name,
                     DB: salsa::Database,
                 {
+                    #lru
+
                     fn execute(db: &DB, #key_pattern: <Self as salsa::Query<DB>>::Key)
                         -> <Self as salsa::Query<DB>>::Value {
                         #invoke(db, #(#key_names),*)
                     }
+
+                    #recover
+
+                    #value_eq
+
+                    #memoize_value
                 }
             });
         }
@@ -366,10 +616,23 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
     });
 
     let mut for_each_ops = proc_macro2::TokenStream::new();
-    for Query { fn_name, .. } in &queries {
+    let mut dependency_graph_ops = proc_macro2::TokenStream::new();
+    for Query {
+        fn_name,
+        query_type,
+        ..
+    } in &queries
+    {
         for_each_ops.extend(quote! {
             op(&self.#fn_name);
         });
+        dependency_graph_ops.extend(quote! {
+            salsa::plumbing::QueryStorageOps::<DB__, #query_type>::dependency_graph(
+                &self.#fn_name,
+                db,
+                graph,
+            );
+        });
     }
 
     // Emit query group storage struct
@@ -409,9 +672,29 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
             ) {
                 #for_each_ops
             }
+
+            #trait_vis fn dependency_graph(
+                &self,
+                db: &DB__,
+                graph: &mut salsa::debug::DependencyGraph<DB__>,
+            ) {
+                #dependency_graph_ops
+            }
         }
     });
 
+    let persistence = persistence(
+        &queries,
+        &trait_name,
+        &group_struct,
+        &group_key,
+        &group_storage,
+        &storage_types,
+    );
+    output.extend(quote! {
+        salsa::__salsa_if_serde! { #persistence }
+    });
+
     if std::env::var("SALSA_DUMP").is_ok() {
         println!("~~~ query_group");
         println!("{}", output.to_string());
@@ -429,6 +712,88 @@ fn is_salsa_attr_path(path: &syn::Path) -> bool {
         || path.segments.len() != 2
 }
 
+/// Emits the impls needed to save and load the queries of the group
+/// with salsa's `serde` feature. They only apply if all keys and
+/// values of the group can be serialized, which is why they are
+/// generic over the database and name the key types through `Query`:
+/// unsatisfiable bounds on concrete types would be an error.
+fn persistence(
+    queries: &[Query],
+    trait_name: &Ident,
+    group_struct: &Ident,
+    group_key: &Ident,
+    group_storage: &Ident,
+    storage_types: &[proc_macro2::TokenStream],
+) -> proc_macro2::TokenStream {
+    let mut key_bounds = proc_macro2::TokenStream::new();
+    let mut encode_arms = proc_macro2::TokenStream::new();
+    let mut decode_arms = proc_macro2::TokenStream::new();
+    let mut for_each_ops = proc_macro2::TokenStream::new();
+    for (i, query) in queries.iter().enumerate() {
+        let fn_name = &query.fn_name;
+        let qt = &query.query_type;
+        let tag = proc_macro2::Literal::u32_suffixed(i as u32);
+        key_bounds.extend(quote! {
+            <#qt as salsa::Query<DB__>>::Key: salsa::plumbing::serde::Serialize
+                + salsa::plumbing::serde::de::DeserializeOwned,
+        });
+        encode_arms.extend(quote! {
+            #group_key::#fn_name(key) => salsa::plumbing::encode_tagged(
+                #tag,
+                salsa::plumbing::encode::<<#qt as salsa::Query<DB__>>::Key>(key),
+            ),
+        });
+        decode_arms.extend(quote! {
+            #tag => Ok(#group_key::#fn_name(
+                salsa::plumbing::decode::<<#qt as salsa::Query<DB__>>::Key>(bytes)?,
+            )),
+        });
+        for_each_ops.extend(quote! {
+            op(stringify!(#fn_name), &self.#fn_name);
+        });
+    }
+
+    quote! {
+        impl<DB__> salsa::plumbing::PersistentDatabaseKey<DB__> for #group_key
+        where
+            DB__: #trait_name,
+            DB__: salsa::plumbing::HasQueryGroup<#group_struct>,
+            #key_bounds
+        {
+            fn encode(&self) -> std::io::Result<Vec<u8>> {
+                match self {
+                    #encode_arms
+                }
+            }
+
+            fn decode(bytes: &[u8]) -> std::io::Result<Self> {
+                let (tag, bytes) = salsa::plumbing::decode_tag(bytes)?;
+                match tag {
+                    #decode_arms
+                    _ => Err(salsa::plumbing::invalid_data("unknown query in database key")),
+                }
+            }
+        }
+
+        impl<DB__> salsa::plumbing::PersistentQueryGroup<DB__> for #group_storage<DB__>
+        where
+            DB__: #trait_name,
+            DB__: salsa::plumbing::HasQueryGroup<#group_struct>,
+            #(#storage_types: salsa::plumbing::PersistentQueryStorageOps<DB__>,)*
+        {
+            fn for_each_persistent_query(
+                &self,
+                op: &mut dyn FnMut(
+                    &'static str,
+                    &dyn salsa::plumbing::PersistentQueryStorageOps<DB__>,
+                ),
+            ) {
+                #for_each_ops
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Query {
     fn_name: Ident,
@@ -438,12 +803,48 @@ struct Query {
     keys: Vec<syn::Type>,
     value: syn::Type,
     invoke: Option<syn::Path>,
+    cycle: Option<syn::Path>,
+    eq_with: Option<syn::Path>,
+    no_eq: bool,
+    memoize_if: Option<syn::Path>,
+    lru: Option<syn::LitInt>,
+    heap_size: bool,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum QueryStorage {
     Memoized,
     Volatile,
     Dependencies,
+    Hashed,
     Input,
+    Interned,
+    InternedLookup { intern_query_type: Ident },
+}
+
+impl QueryStorage {
+    /// True for queries whose value is computed by a function.
+    fn is_derived(&self) -> bool {
+        match self {
+            QueryStorage::Memoized
+            | QueryStorage::Volatile
+            | QueryStorage::Dependencies
+            | QueryStorage::Hashed => true,
+            QueryStorage::Input | QueryStorage::Interned | QueryStorage::InternedLookup { .. } => {
+                false
+            }
+        }
+    }
+
+    /// The name of the `#[salsa::XXX]` attribute selecting this storage.
+    fn attribute_name(&self) -> &'static str {
+        match self {
+            QueryStorage::Memoized => "memoized",
+            QueryStorage::Volatile => "volatile",
+            QueryStorage::Dependencies => "dependencies",
+            QueryStorage::Hashed => "hashed",
+            QueryStorage::Input => "input",
+            QueryStorage::Interned | QueryStorage::InternedLookup { .. } => "interned",
+        }
+    }
 }
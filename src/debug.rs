@@ -3,8 +3,13 @@
 
 use crate::plumbing;
 use crate::plumbing::QueryStorageOps;
+use crate::runtime::Revision;
+use crate::Database;
 use crate::Query;
 use crate::QueryTable;
+use rustc_hash::FxHashMap;
+use std::any::TypeId;
+use std::fmt::Write;
 use std::iter::FromIterator;
 
 /// Additional methods on queries that can be used to "peek into"
@@ -67,3 +72,113 @@ where
         self.storage.entries(self.db)
     }
 }
+
+/// Returns the dependency graph of all queries stored in `db`, in the
+/// Graphviz DOT format. There is a node for each input and for each
+/// memoized query, labelled with its query and key and annotated with
+/// the revisions in which it was last verified and last changed, and
+/// an edge from each memoized query to each of the inputs it read.
+pub fn dependency_graph<DB: Database>(db: &DB) -> String {
+    let mut graph = DependencyGraph {
+        dot: String::from("digraph salsa {\n"),
+        queries: FxHashMap::default(),
+        nodes: FxHashMap::default(),
+        edges: Vec::new(),
+    };
+    db.fill_dependency_graph(&mut graph);
+
+    // The inputs of a query may be added after it, so the edges are
+    // only written once all nodes are known.
+    for (from, input) in std::mem::take(&mut graph.edges) {
+        let to = match graph.nodes.get(&input) {
+            Some(to) => to.clone(),
+            // E.g., the lookups of interned values, which have no
+            // entries of their own.
+            None => {
+                let id = format!("k{}", graph.nodes.len());
+                let label = format!("{:?}", input);
+                writeln!(graph.dot, "    {} [label={}];", id, quoted(&label)).unwrap();
+                graph.nodes.insert(input, id.clone());
+                id
+            }
+        };
+        writeln!(graph.dot, "    {} -> {};", from, to).unwrap();
+    }
+    graph.dot.push_str("}\n");
+    graph.dot
+}
+
+/// The graph built by `dependency_graph`, to which each query storage
+/// adds its entries.
+///
+/// The nodes are identified by the index of their query and their index
+/// within that query, not by their `Debug` output, which distinct keys
+/// may share.
+pub struct DependencyGraph<DB: Database> {
+    dot: String,
+    /// The index of each query and the number of nodes added for it.
+    queries: FxHashMap<TypeId, (usize, usize)>,
+    nodes: FxHashMap<DB::DatabaseKey, String>,
+    edges: Vec<(String, DB::DatabaseKey)>,
+}
+
+impl<DB: Database> DependencyGraph<DB> {
+    /// Adds the node for the entry `key` of the query `Q`, returning its
+    /// identifier. `verified_at` is `None` for values that are never
+    /// revalidated (e.g., inputs).
+    pub(crate) fn add_node<Q: Query<DB>>(
+        &mut self,
+        db: &DB,
+        key: &Q::Key,
+        verified_at: Option<Revision>,
+        changed_at: Revision,
+        untracked: bool,
+    ) -> String
+    where
+        DB: plumbing::HasQueryGroup<Q::Group>,
+    {
+        let next_query = self.queries.len();
+        let (query_index, entries) = self
+            .queries
+            .entry(TypeId::of::<Q>())
+            .or_insert((next_query, 0));
+        let id = format!("q{}_{}", query_index, entries);
+        *entries += 1;
+
+        let mut label = format!("{:?}({:?})", Q::default(), key);
+        if let Some(verified_at) = verified_at {
+            write!(label, "\nverified_at: {:?}", verified_at).unwrap();
+        }
+        write!(label, "\nchanged_at: {:?}", changed_at).unwrap();
+        if untracked {
+            label.push_str("\nuntracked");
+        }
+        writeln!(self.dot, "    {} [label={}];", id, quoted(&label)).unwrap();
+
+        let database_key = <DB as plumbing::GetQueryTable<Q>>::database_key(db, key.clone());
+        self.nodes.insert(database_key, id.clone());
+        id
+    }
+
+    /// Adds an edge from the node `from` (as returned by `add_node`)
+    /// to the node for `input`.
+    pub(crate) fn add_edge(&mut self, from: &str, input: &DB::DatabaseKey) {
+        self.edges.push((from.to_string(), input.clone()));
+    }
+}
+
+fn quoted(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -1,6 +1,12 @@
+use crate::debug::DependencyGraph;
 use crate::debug::TableEntry;
+use crate::lru::Lru;
+#[cfg(feature = "serde")]
+use crate::persist::{self, PersistentDatabaseKey, PersistentQueryStorageOps};
 use crate::plumbing::CycleDetected;
 use crate::plumbing::DatabaseKey;
+use crate::plumbing::HasQueryGroup;
+use crate::plumbing::LruQueryStorageOps;
 use crate::plumbing::QueryFunction;
 use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
@@ -10,27 +16,49 @@ use crate::runtime::Revision;
 use crate::runtime::Runtime;
 use crate::runtime::RuntimeId;
 use crate::runtime::StampedValue;
-use crate::{Database, DiscardIf, DiscardWhat, Event, EventKind, SweepStrategy};
+use crate::{
+    Cycle, Database, DiscardIf, DiscardWhat, Durability, Event, EventKind, ExecuteReason,
+    QueryMemoryUsage, SweepStrategy,
+};
 use log::{debug, info};
 use parking_lot::Mutex;
 use parking_lot::RwLock;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHasher};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use smallvec::SmallVec;
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "serde")]
+use std::io;
 use std::marker::PhantomData;
+use std::mem::size_of;
 use std::ops::Deref;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
 
 /// Memoized queries store the result plus a list of the other queries
 /// that they invoked. This means we can avoid recomputing them when
 /// none of those inputs have changed.
 pub type MemoizedStorage<DB, Q> = DerivedStorage<DB, Q, AlwaysMemoizeValue>;
 
+/// Like memoized queries, but values are compared with
+/// `QueryFunction::value_eq` rather than `Eq` (see
+/// `#[salsa::eq_with]` and `#[salsa::no_eq]`).
+pub type CustomEqStorage<DB, Q> = DerivedStorage<DB, Q, CustomEqValue>;
+
 /// "Dependency" queries just track their dependencies and not the
 /// actual value (which they produce on demand). This lessens the
 /// storage requirements.
 pub type DependencyStorage<DB, Q> = DerivedStorage<DB, Q, NeverMemoizeValue>;
 
+/// "Hashed" queries are like "dependency" queries, but they also
+/// store a hash of their value. If they are re-executed and produce a
+/// value with the same hash, queries that depend on them need not be
+/// re-executed.
+pub type HashedStorage<DB, Q> = DerivedStorage<DB, Q, HashValue>;
+
 /// "Dependency" queries just track their dependencies and not the
 /// actual value (which they produce on demand). This lessens the
 /// storage requirements.
@@ -45,6 +73,7 @@ where
     MP: MemoizationPolicy<DB, Q>,
 {
     map: RwLock<FxHashMap<Q::Key, QueryState<DB, Q>>>,
+    lru: Lru<Q::Key>,
     policy: PhantomData<MP>,
 }
 
@@ -68,6 +97,13 @@ where
     fn memoized_value_eq(old_value: &Q::Value, new_value: &Q::Value) -> bool;
 
     fn should_track_inputs(key: &Q::Key) -> bool;
+
+    /// A hash of `value`, stored in place of the value itself when it
+    /// is not memoized; two values with the same hash are considered
+    /// equal.
+    fn fingerprint(_value: &Q::Value) -> Option<u64> {
+        None
+    }
 }
 
 pub enum AlwaysMemoizeValue {}
@@ -77,8 +113,8 @@ where
     Q::Value: Eq,
     DB: Database,
 {
-    fn should_memoize_value(_key: &Q::Key) -> bool {
-        true
+    fn should_memoize_value(key: &Q::Key) -> bool {
+        Q::memoize_value(key)
     }
 
     fn memoized_value_eq(old_value: &Q::Value, new_value: &Q::Value) -> bool {
@@ -90,6 +126,25 @@ where
     }
 }
 
+pub enum CustomEqValue {}
+impl<DB, Q> MemoizationPolicy<DB, Q> for CustomEqValue
+where
+    Q: QueryFunction<DB>,
+    DB: Database,
+{
+    fn should_memoize_value(key: &Q::Key) -> bool {
+        Q::memoize_value(key)
+    }
+
+    fn memoized_value_eq(old_value: &Q::Value, new_value: &Q::Value) -> bool {
+        Q::value_eq(old_value, new_value)
+    }
+
+    fn should_track_inputs(_key: &Q::Key) -> bool {
+        true
+    }
+}
+
 pub enum NeverMemoizeValue {}
 impl<DB, Q> MemoizationPolicy<DB, Q> for NeverMemoizeValue
 where
@@ -109,6 +164,34 @@ where
     }
 }
 
+pub enum HashValue {}
+impl<DB, Q> MemoizationPolicy<DB, Q> for HashValue
+where
+    Q: QueryFunction<DB>,
+    Q::Value: Hash,
+    DB: Database,
+{
+    fn should_memoize_value(_key: &Q::Key) -> bool {
+        false
+    }
+
+    fn memoized_value_eq(_old_value: &Q::Value, _new_value: &Q::Value) -> bool {
+        panic!("cannot reach since we never memoize")
+    }
+
+    fn should_track_inputs(_key: &Q::Key) -> bool {
+        true
+    }
+
+    fn fingerprint(value: &Q::Value) -> Option<u64> {
+        // `FxHasher` is deterministic, so fingerprints remain valid
+        // when memos are persisted.
+        let mut hasher = FxHasher::default();
+        value.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+}
+
 pub enum VolatileValue {}
 impl<DB, Q> MemoizationPolicy<DB, Q> for VolatileValue
 where
@@ -144,13 +227,28 @@ where
     /// indeeds a cycle.
     InProgress {
         id: RuntimeId,
-        waiting: Mutex<SmallVec<[Sender<StampedValue<Q::Value>>; 2]>>,
+        waiting: Mutex<SmallVec<[Waiter<Q::Value>; 2]>>,
     },
 
     /// We have computed the query already, and here is the result.
     Memoized(Memo<DB, Q>),
 }
 
+/// Someone waiting for a runtime to finish computing a value.
+enum Waiter<V> {
+    /// A thread, blocked on the receiving half of the channel. It
+    /// receives `None` if the runtime was preempted (see
+    /// `Runtime::set_snapshot_priority`), and should then compute the
+    /// value itself.
+    Thread(Sender<Option<StampedValue<V>>>),
+
+    /// An asynchronous task (see `QueryTable::get_async`), which is
+    /// woken to fetch the value once it is available. Each future has
+    /// a runtime of its own, which identifies its waker when it is
+    /// polled again.
+    Task(RuntimeId, Waker),
+}
+
 impl<DB, Q> QueryState<DB, Q>
 where
     Q: QueryFunction<DB>,
@@ -179,6 +277,10 @@ where
     /// The result of the query, if we decide to memoize it.
     value: Option<Q::Value>,
 
+    /// A hash of the result of the query, if the memoization policy
+    /// stores one (see `MemoizationPolicy::fingerprint`).
+    fingerprint: Option<u64>,
+
     /// Last revision when this memo was verified (if there are
     /// untracked inputs, this will also be when the memo was
     /// created).
@@ -187,6 +289,9 @@ where
     /// Last revision when the memoized value was observed to change.
     changed_at: Revision,
 
+    /// Minimum durability of the inputs to this query.
+    durability: Durability,
+
     /// The inputs that went into our query, if we are tracking them.
     inputs: MemoInputs<DB>,
 }
@@ -237,14 +342,15 @@ where
     fn default() -> Self {
         DerivedStorage {
             map: RwLock::new(FxHashMap::default()),
+            lru: Lru::new(Q::LRU_CAPACITY),
             policy: PhantomData,
         }
     }
 }
 
 /// Return value of `probe` helper.
-enum ProbeState<V, G> {
-    UpToDate(Result<V, CycleDetected>),
+enum ProbeState<V, G, DB: Database> {
+    UpToDate(Result<V, Cycle<DB>>),
     StaleOrAbsent(G),
 }
 
@@ -259,7 +365,7 @@ where
         db: &DB,
         key: &Q::Key,
         database_key: &DB::DatabaseKey,
-    ) -> Result<StampedValue<Q::Value>, CycleDetected> {
+    ) -> Result<StampedValue<Q::Value>, Cycle<DB>> {
         let runtime = db.salsa_runtime();
 
         // NB: We don't need to worry about people modifying the
@@ -292,6 +398,29 @@ where
         self.read_upgrade(db, key, database_key, revision_now)
     }
 
+    /// Discards the memoized values for `keys`, which were evicted
+    /// from the LRU. The memos themselves are kept, so their inputs
+    /// can still be used to tell whether they changed.
+    fn evict(&self, keys: Vec<Q::Key>) {
+        if keys.is_empty() {
+            return;
+        }
+
+        let mut map_write = self.map.write();
+        for key in keys {
+            if let Some(QueryState::Memoized(memo)) = map_write.get_mut(&key) {
+                // Values with untracked inputs cannot be recomputed
+                // reliably within the same revision, so keep them.
+                if let MemoInputs::Untracked = memo.inputs {
+                    continue;
+                }
+
+                debug!("evict({:?}({:?}))", Q::default(), key);
+                memo.value = None;
+            }
+        }
+    }
+
     /// Second phase of a read operation: acquires an upgradable-read
     /// and -- if needed -- validates whether inputs have changed,
     /// recomputes value, etc. This is invoked after our initial probe
@@ -302,7 +431,7 @@ where
         key: &Q::Key,
         database_key: &DB::DatabaseKey,
         revision_now: Revision,
-    ) -> Result<StampedValue<Q::Value>, CycleDetected> {
+    ) -> Result<StampedValue<Q::Value>, Cycle<DB>> {
         let runtime = db.salsa_runtime();
 
         debug!(
@@ -312,6 +441,10 @@ where
             revision_now,
         );
 
+        // Check for cancellation before we install our placeholder, so
+        // that unwinding does not discard the old memo.
+        runtime.unwind_if_automatically_canceled();
+
         // Check with an upgradable read to see if there is a value
         // already. (This permits other readers but prevents anyone
         // else from running `read_upgrade` at the same time.)
@@ -343,8 +476,12 @@ where
         // has been a new revision since the last time we checked. So,
         // first things first, let's walk over each of our previous
         // inputs and check whether they are out of date.
-        if let Some(memo) = &mut old_memo {
-            if let Some(value) = memo.validate_memoized_value(db, revision_now) {
+        let validated = match &mut old_memo {
+            Some(memo) => memo.validate_memoized_value(db, revision_now),
+            None => Err(ExecuteReason::NotMemoized),
+        };
+        let reason = match validated {
+            Ok(value) => {
                 info!(
                     "{:?}({:?}): validated old memoized value",
                     Q::default(),
@@ -358,24 +495,65 @@ where
                     },
                 });
 
+                runtime
+                    .query_stats()
+                    .record::<Q>(|stats| stats.validations += 1);
+
                 panic_guard.proceed(old_memo.unwrap(), &value);
 
                 return Ok(value);
             }
-        }
+            Err(reason) => reason,
+        };
 
         // Query was not previously executed, or value is potentially
         // stale, or value is absent. Let's execute!
-        let mut result = runtime.execute_query_implementation(db, database_key, || {
-            info!("{:?}({:?}): executing query", Q::default(), key);
+        let started_at = Instant::now();
+        let mut result = runtime.execute_query_implementation(
+            db,
+            database_key,
+            reason,
+            Q::CYCLE_RECOVERY,
+            || {
+                info!("{:?}({:?}): executing query", Q::default(), key);
 
-            if !self.should_track_inputs(key) {
-                runtime.report_untracked_read();
-            }
+                if !self.should_track_inputs(key) {
+                    runtime.report_untracked_read();
+                }
 
-            Q::execute(db, key.clone())
+                if Q::CYCLE_RECOVERY {
+                    runtime.catch_cycle(|| Q::execute(db, key.clone()))
+                } else {
+                    Some(Q::execute(db, key.clone()))
+                }
+            },
+        );
+        runtime.query_stats().record::<Q>(|stats| {
+            stats.executions += 1;
+            stats.execution_time += started_at.elapsed();
         });
 
+        // If the query took part in a cycle, then its result was
+        // computed from a fallback value; use its own fallback value
+        // too (if it has one), so that the result does not depend on
+        // which query of the cycle was invoked first.
+        if !result.cycle.is_empty() {
+            let cycle = Cycle::new(std::mem::take(&mut result.cycle));
+            if let Some(value) = Q::recover(db, &cycle, key) {
+                debug!(
+                    "read_upgrade({:?}({:?})): recovered from {:?}",
+                    Q::default(),
+                    key,
+                    cycle,
+                );
+                result.value = Some(value);
+            }
+        }
+
+        // The query only unwinds to here (and has no value) if it can
+        // recover from the cycle that it takes part in.
+        let value = result.value.unwrap();
+
         // We assume that query is side-effect free -- that is, does
         // not mutate the "inputs" to the query system. Sanity check
         // that assumption here, at least to the best of our ability.
@@ -388,25 +566,30 @@ where
         // If the new value is equal to the old one, then it didn't
         // really change, even if some of its inputs have. So we can
         // "backdate" its `changed_at` revision to be the same as the
-        // old value.
+        // old value. Without an old value, we compare the hashes of
+        // the values instead, if we have them.
+        let fingerprint = MP::fingerprint(&value);
         if let Some(old_memo) = &old_memo {
-            if let Some(old_value) = &old_memo.value {
-                if MP::memoized_value_eq(&old_value, &result.value) {
-                    debug!(
-                        "read_upgrade({:?}({:?})): value is equal, back-dating to {:?}",
-                        Q::default(),
-                        key,
-                        old_memo.changed_at,
-                    );
+            let value_eq = match (&old_memo.value, old_memo.fingerprint) {
+                (Some(old_value), _) => MP::memoized_value_eq(old_value, &value),
+                (None, Some(old_fingerprint)) => fingerprint == Some(old_fingerprint),
+                (None, None) => false,
+            };
+            if value_eq {
+                debug!(
+                    "read_upgrade({:?}({:?})): value is equal, back-dating to {:?}",
+                    Q::default(),
+                    key,
+                    old_memo.changed_at,
+                );
 
-                    assert!(old_memo.changed_at <= result.changed_at.revision);
-                    result.changed_at.revision = old_memo.changed_at;
-                }
+                assert!(old_memo.changed_at <= result.changed_at.revision);
+                result.changed_at.revision = old_memo.changed_at;
             }
         }
 
         let new_value = StampedValue {
-            value: result.value,
+            value,
             changed_at: result.changed_at,
         };
 
@@ -449,7 +632,9 @@ where
         panic_guard.proceed(
             Memo {
                 value,
+                fingerprint,
                 changed_at: result.changed_at.revision,
+                durability: result.changed_at.durability,
                 verified_at: revision_now,
                 inputs,
             },
@@ -468,11 +653,9 @@ where
     ///
     /// - `ProbeState::UpToDate(r)` if the table has an up-to-date
     ///   value (or we blocked on another thread that produced such a value).
-    /// - `ProbeState::CycleDetected` if this thread is (directly or
-    ///   indirectly) already computing this value.
-    /// - `ProbeState::BlockedOnOtherThread` if some other thread
-    ///   (which does not depend on us) was already computing this
-    ///   value; caller should re-acquire the lock and try again.
+    ///   If this thread is (directly or indirectly) already computing this
+    ///   value, `r` is the value recovered from the cycle or `Err` if the
+    ///   query cannot recover.
     /// - `ProbeState::StaleOrAbsent` if either (a) there is no memo
     ///    for this key, (b) the memo has no value; or (c) the memo
     ///    has not been verified at the current revision.
@@ -487,7 +670,7 @@ where
         revision_now: Revision,
         database_key: &DB::DatabaseKey,
         key: &Q::Key,
-    ) -> ProbeState<StampedValue<Q::Value>, MapGuard>
+    ) -> ProbeState<StampedValue<Q::Value>, MapGuard, DB>
     where
         MapGuard: Deref<Target = FxHashMap<Q::Key, QueryState<DB, Q>>>,
     {
@@ -513,11 +696,49 @@ where
                             },
                         });
 
-                        let value = rx.recv().unwrap_or_else(|_| db.on_propagated_panic());
-                        ProbeState::UpToDate(Ok(value))
+                        runtime
+                            .query_stats()
+                            .record::<Q>(|stats| stats.blocked += 1);
+
+                        let result = rx.recv();
+
+                        // If another runtime found that we are part of a
+                        // cycle, it will have recorded the cycle for us.
+                        match (result, runtime.take_blocked_cycle()) {
+                            (Ok(Some(value)), _) => ProbeState::UpToDate(Ok(value)),
+                            (Ok(None), _) => ProbeState::UpToDate(self.read(db, key, database_key)),
+                            (Err(_), Some(cycle)) => ProbeState::UpToDate(self.recover(
+                                db,
+                                key,
+                                database_key,
+                                revision_now,
+                                cycle,
+                            )),
+                            (Err(_), None) => {
+                                // If the revision was canceled, then
+                                // that is presumably why the other
+                                // runtime unwound.
+                                runtime.unwind_if_canceled();
+                                db.on_propagated_panic()
+                            }
+                        }
                     }
 
-                    Err(CycleDetected) => ProbeState::UpToDate(Err(CycleDetected)),
+                    Err(error) => {
+                        // Release our lock on `self.map` before invoking
+                        // the recovery function, which may execute
+                        // further queries.
+                        std::mem::drop(map);
+
+                        let cycle = runtime.mark_cycle_participants(database_key, error);
+                        ProbeState::UpToDate(self.recover(
+                            db,
+                            key,
+                            database_key,
+                            revision_now,
+                            cycle,
+                        ))
+                    }
                 };
             }
 
@@ -532,6 +753,8 @@ where
                         value.changed_at
                     );
 
+                    runtime.query_stats().record::<Q>(|stats| stats.hits += 1);
+
                     return ProbeState::UpToDate(Ok(value));
                 }
             }
@@ -542,6 +765,41 @@ where
         ProbeState::StaleOrAbsent(map)
     }
 
+    /// Helper for `probe`:
+    ///
+    /// Invoked when fetching `key` was found to be part of `cycle`;
+    /// returns the value given by the query's recovery function. If
+    /// the query cannot recover, unwinds to another participant that
+    /// can or, if there is none, returns the cycle itself.
+    ///
+    /// The recovered value stands in for a value that depends on the
+    /// other participants, whose durability is not known yet; so it is
+    /// assumed to have changed in this revision, with low durability.
+    fn recover(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        revision_now: Revision,
+        cycle: Cycle<DB>,
+    ) -> Result<StampedValue<Q::Value>, Cycle<DB>> {
+        match Q::recover(db, &cycle, key) {
+            Some(value) => Ok(StampedValue {
+                value,
+                changed_at: ChangedAt {
+                    is_constant: false,
+                    durability: Durability::Low,
+                    revision: revision_now,
+                },
+            }),
+            None => {
+                db.salsa_runtime()
+                    .unwind_to_cycle_recovery(&cycle, database_key);
+                Err(cycle)
+            }
+        }
+    }
+
     /// Helper:
     ///
     /// When we encounter an `InProgress` indicator, we need to either
@@ -554,20 +812,22 @@ where
         runtime: &Runtime<DB>,
         database_key: &DB::DatabaseKey,
         other_id: RuntimeId,
-        waiting: &Mutex<SmallVec<[Sender<StampedValue<Q::Value>>; 2]>>,
-    ) -> Result<Receiver<StampedValue<Q::Value>>, CycleDetected> {
-        if other_id == runtime.id() {
-            return Err(CycleDetected);
+        waiting: &Mutex<SmallVec<[Waiter<Q::Value>; 2]>>,
+    ) -> Result<Receiver<Option<StampedValue<Q::Value>>>, CycleDetected> {
+        if runtime.is_self_or_forked_from(other_id) {
+            return Err(CycleDetected {
+                from: other_id,
+                to: other_id,
+            });
         } else {
-            if !runtime.try_block_on(database_key, other_id) {
-                return Err(CycleDetected);
-            }
+            runtime.try_block_on(database_key, other_id)?;
+            runtime.preempt_if_lower_priority(other_id);
 
             let (tx, rx) = mpsc::channel();
 
             // The reader of this will have to acquire map
             // lock, we don't need any particular ordering.
-            waiting.lock().push(tx);
+            waiting.lock().push(Waiter::Thread(tx));
 
             Ok(rx)
         }
@@ -648,20 +908,26 @@ where
                 self.runtime
                     .unblock_queries_blocked_on_self(self.database_key);
 
-                match new_value {
-                    // If anybody has installed themselves in our "waiting"
-                    // list, notify them that the value is available.
-                    Some(new_value) => {
-                        for tx in waiting.into_inner() {
-                            tx.send(new_value.clone()).unwrap()
+                // If anybody has installed themselves in our "waiting"
+                // list, notify them that the value is available.
+                //
+                // We have no value to send when we are panicking.
+                // Therefore, we drop the sending half of the channel so
+                // that our panic propagates to those waiting on the
+                // receiving half -- unless we were preempted, in which
+                // case they compute the value themselves. Tasks are
+                // woken either way; they will find the value, or else
+                // compute it themselves.
+                let preempted = self.runtime.take_preempted();
+                for waiter in waiting.into_inner() {
+                    match (waiter, new_value) {
+                        (Waiter::Thread(tx), Some(new_value)) => {
+                            tx.send(Some(new_value.clone())).unwrap()
                         }
+                        (Waiter::Thread(tx), None) if preempted => tx.send(None).unwrap(),
+                        (Waiter::Thread(tx), None) => std::mem::drop(tx),
+                        (Waiter::Task(_, waker), _) => waker.wake(),
                     }
-
-                    // We have no value to send when we are panicking.
-                    // Therefore, we need to drop the sending half of the
-                    // channel so that our panic propagates to those waiting
-                    // on the receiving half.
-                    None => std::mem::drop(waiting),
                 }
             }
             _ => panic!(
@@ -702,15 +968,71 @@ where
         db: &DB,
         key: &Q::Key,
         database_key: &DB::DatabaseKey,
-    ) -> Result<Q::Value, CycleDetected> {
+    ) -> Result<Q::Value, Cycle<DB>> {
         let StampedValue { value, changed_at } = self.read(db, key, &database_key)?;
 
+        let evicted = self.lru.record_use(key);
+        self.evict(evicted);
+
         db.salsa_runtime()
             .report_query_read(database_key, changed_at);
 
         Ok(value)
     }
 
+    fn poll_fetch(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Q::Value, Cycle<DB>>> {
+        let runtime = db.salsa_runtime();
+
+        // If another runtime is computing the value, register to be
+        // woken once it is done, rather than blocking on it. We hold
+        // the read lock on `self.map` while doing so, and the other
+        // runtime needs the write lock to finish, so we cannot miss
+        // the wake-up.
+        if let Some(QueryState::InProgress { id, waiting }) = self.map.read().get(key) {
+            if *id != runtime.id() {
+                runtime.preempt_if_lower_priority(*id);
+
+                // If the future was polled before, it is already
+                // waiting: just make sure that the current waker is
+                // the one that is woken.
+                let mut waiting = waiting.lock();
+                let registered = waiting.iter_mut().find_map(|waiter| match waiter {
+                    Waiter::Task(waiter_id, waker) if *waiter_id == runtime.id() => Some(waker),
+                    _ => None,
+                });
+                if let Some(waker) = registered {
+                    if !waker.will_wake(cx.waker()) {
+                        *waker = cx.waker().clone();
+                    }
+                    return Poll::Pending;
+                }
+
+                db.salsa_event(|| Event {
+                    runtime_id: runtime.id(),
+                    kind: EventKind::WillBlockOn {
+                        other_runtime_id: *id,
+                        database_key: database_key.clone(),
+                    },
+                });
+
+                runtime
+                    .query_stats()
+                    .record::<Q>(|stats| stats.blocked += 1);
+
+                waiting.push(Waiter::Task(runtime.id(), cx.waker().clone()));
+                return Poll::Pending;
+            }
+        }
+
+        Poll::Ready(self.try_fetch(db, key, database_key))
+    }
+
     fn maybe_changed_since(
         &self,
         db: &DB,
@@ -769,12 +1091,32 @@ where
                         // can complete.
                         std::mem::drop(map);
 
-                        let value = rx.recv().unwrap_or_else(|_| db.on_propagated_panic());
-                        return value.changed_at.changed_since(revision);
+                        runtime
+                            .query_stats()
+                            .record::<Q>(|stats| stats.blocked += 1);
+
+                        let result = rx.recv();
+                        return match (result, runtime.take_blocked_cycle()) {
+                            (Ok(Some(value)), _) => value.changed_at.changed_since(revision),
+                            (Ok(None), _) => {
+                                self.maybe_changed_since(db, revision, key, database_key)
+                            }
+
+                            // Consider a cycle to have changed.
+                            (Err(_), Some(_)) => true,
+
+                            (Err(_), None) => {
+                                // If the revision was canceled, then
+                                // that is presumably why the other
+                                // runtime unwound.
+                                runtime.unwind_if_canceled();
+                                db.on_propagated_panic()
+                            }
+                        };
                     }
 
                     // Consider a cycle to have changed.
-                    Err(CycleDetected) => return true,
+                    Err(_) => return true,
                 }
             }
 
@@ -792,7 +1134,25 @@ where
             return memo.changed_at > revision;
         }
 
+        // Set if the memo itself changed after `revision`, even though
+        // none of its inputs changed since it was last verified.
+        let mut memo_changed = false;
+
         let inputs = match &memo.inputs {
+            // If no input with (at least) our durability changed
+            // since we were last verified, there is nothing to
+            // check; we just need to update `verified_at` below.
+            MemoInputs::Tracked { .. } if memo.check_durability(runtime) => {
+                debug!(
+                    "maybe_changed_since({:?}({:?}): no input of durability {:?} changed",
+                    Q::default(),
+                    key,
+                    memo.durability,
+                );
+                memo_changed = memo.changed_at > revision;
+                None
+            }
+
             MemoInputs::Untracked => {
                 // we don't know the full set of
                 // inputs, so if there is a new
@@ -812,9 +1172,11 @@ where
                 // At this point, the value may be dirty (we have
                 // to check the database-keys). If we have a cached
                 // value, we'll just fall back to invoking `read`,
-                // which will do that checking (and a bit more) --
-                // note that we skip the "pure read" part as we
-                // already know the result.
+                // which will do that checking (and a bit more) -- note
+                // that we skip the "pure read" part as we already know
+                // the result. (With only the hash of the value, `read`
+                // would always re-execute the query, so we check the
+                // inputs first; see below.)
                 assert!(inputs.len() > 0);
                 if memo.value.is_some() {
                     std::mem::drop(map);
@@ -829,7 +1191,7 @@ where
                             );
                             v.changed_at.changed_since(revision)
                         }
-                        Err(CycleDetected) => true,
+                        Err(_) => true,
                     };
                 }
 
@@ -864,6 +1226,7 @@ where
         // could have happened in the interim. =) Therefore, we have
         // to probe the current state of `key` and in some cases we
         // ought to do nothing.
+        let mut reexecute = false;
         {
             let mut map = self.map.write();
             match map.get_mut(key) {
@@ -882,6 +1245,11 @@ where
                         // less efficient? (It may cause some
                         // downstream value to be recomputed that
                         // wouldn't otherwise have to be?)
+                    } else if maybe_changed && memo.fingerprint.is_some() {
+                        // We found this entry is out of date, but the
+                        // new value may hash the same: keep it, so
+                        // that re-executing the query below can tell.
+                        reexecute = true;
                     } else if maybe_changed {
                         // We found this entry is out of date and
                         // nobody touch it in the meantime. Just
@@ -910,7 +1278,23 @@ where
             }
         }
 
-        maybe_changed
+        if reexecute {
+            return match self.read_upgrade(db, key, database_key, revision_now) {
+                Ok(v) => {
+                    debug!(
+                        "maybe_changed_since({:?}({:?}): {:?} since (recomputed) hash changed at {:?}",
+                        Q::default(),
+                        key,
+                        v.changed_at.changed_since(revision),
+                        v.changed_at,
+                    );
+                    v.changed_at.changed_since(revision)
+                }
+                Err(_) => true,
+            };
+        }
+
+        maybe_changed || memo_changed
     }
 
     fn is_constant(&self, _db: &DB, key: &Q::Key) -> bool {
@@ -931,6 +1315,29 @@ where
             .map(|(key, query_state)| TableEntry::new(key.clone(), query_state.value()))
             .collect()
     }
+
+    fn dependency_graph(&self, db: &DB, graph: &mut DependencyGraph<DB>)
+    where
+        DB: HasQueryGroup<Q::Group>,
+    {
+        let map_read = self.map.read();
+        for (key, query_state) in map_read.iter() {
+            // Queries that are in progress have no inputs yet.
+            let memo = match query_state {
+                QueryState::InProgress { .. } => continue,
+                QueryState::Memoized(memo) => memo,
+            };
+
+            let untracked = matches!(memo.inputs, MemoInputs::Untracked);
+            let node =
+                graph.add_node::<Q>(db, key, Some(memo.verified_at), memo.changed_at, untracked);
+            if let MemoInputs::Tracked { inputs } = &memo.inputs {
+                for input in inputs.iter() {
+                    graph.add_edge(&node, input);
+                }
+            }
+        }
+    }
 }
 
 impl<DB, Q, MP> QueryStorageMassOps<DB> for DerivedStorage<DB, Q, MP>
@@ -996,6 +1403,46 @@ where
             }
         });
     }
+
+    fn memory_usage(&self, _db: &DB) -> Option<QueryMemoryUsage> {
+        let map_read = self.map.read();
+        let mut usage = QueryMemoryUsage {
+            query: format!("{:?}", Q::default()),
+            entries: map_read.len(),
+            ..QueryMemoryUsage::default()
+        };
+        for (key, query_state) in map_read.iter() {
+            usage.key_bytes += size_of::<Q::Key>() + Q::key_heap_size(key);
+
+            let memo = match query_state {
+                QueryState::InProgress { .. } => continue,
+                QueryState::Memoized(memo) => memo,
+            };
+            if let Some(value) = &memo.value {
+                usage.value_bytes += size_of::<Q::Value>() + Q::value_heap_size(value);
+            }
+            if let MemoInputs::Tracked { inputs } = &memo.inputs {
+                // Each slot of the set stores an input along with its
+                // hash, and the hash table stores its index. (An
+                // estimate: the exact layout is up to `indexmap`.)
+                usage.inputs_bytes +=
+                    inputs.capacity() * (size_of::<DB::DatabaseKey>() + 2 * size_of::<usize>());
+            }
+        }
+        Some(usage)
+    }
+}
+
+impl<DB, Q, MP> LruQueryStorageOps for DerivedStorage<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn set_lru_capacity(&self, new_capacity: usize) {
+        let evicted = self.lru.set_capacity(new_capacity);
+        self.evict(evicted);
+    }
 }
 
 impl<DB, Q> Memo<DB, Q>
@@ -1003,13 +1450,15 @@ where
     Q: QueryFunction<DB>,
     DB: Database,
 {
+    /// Returns the memoized value if none of its inputs changed, or
+    /// why it has to be recomputed otherwise.
     fn validate_memoized_value(
         &mut self,
         db: &DB,
         revision_now: Revision,
-    ) -> Option<StampedValue<Q::Value>> {
+    ) -> Result<StampedValue<Q::Value>, ExecuteReason<DB>> {
         // If we don't have a memoized value, nothing to validate.
-        let value = self.value.as_ref()?;
+        let value = self.value.as_ref().ok_or(ExecuteReason::NoMemoizedValue)?;
 
         assert!(self.verified_at != revision_now);
         let verified_at = self.verified_at;
@@ -1020,16 +1469,29 @@ where
             self.inputs,
         );
 
-        let is_constant = match &mut self.inputs {
+        let is_constant = match &self.inputs {
             // We can't validate values that had untracked inputs; just have to
             // re-execute.
             MemoInputs::Untracked { .. } => {
-                return None;
+                return Err(ExecuteReason::UntrackedInputs);
             }
 
             // Constant: no changed input
             MemoInputs::Constant => true,
 
+            // If no input with (at least) our durability changed
+            // since we were last verified, then none of our inputs
+            // can have changed; no need to check them individually.
+            MemoInputs::Tracked { .. } if self.check_durability(db.salsa_runtime()) => {
+                debug!(
+                    "{:?}::validate_memoized_value: no input of durability {:?} changed",
+                    Q::default(),
+                    self.durability,
+                );
+
+                false
+            }
+
             // Check whether any of our inputs changed since the
             // **last point where we were verified** (not since we
             // last changed). This is important: if we have
@@ -1052,7 +1514,9 @@ where
                         input
                     );
 
-                    return None;
+                    return Err(ExecuteReason::InputChanged {
+                        database_key: input.clone(),
+                    });
                 }
 
                 false
@@ -1060,15 +1524,23 @@ where
         };
 
         self.verified_at = revision_now;
-        Some(StampedValue {
+        Ok(StampedValue {
             changed_at: ChangedAt {
                 is_constant,
+                durability: self.durability,
                 revision: self.changed_at,
             },
             value: value.clone(),
         })
     }
 
+    /// True if no input with (at least) the durability of this memo
+    /// has changed since it was last verified, in which case none of
+    /// its inputs can have changed.
+    fn check_durability(&self, runtime: &Runtime<DB>) -> bool {
+        runtime.last_changed_revision(self.durability) <= self.verified_at
+    }
+
     /// Returns the memoized value *if* it is known to be update in the given revision.
     fn probe_memoized_value(&self, revision_now: Revision) -> Option<StampedValue<Q::Value>> {
         let value = self.value.as_ref()?;
@@ -1087,6 +1559,7 @@ where
             return Some(StampedValue {
                 changed_at: ChangedAt {
                     is_constant,
+                    durability: self.durability,
                     revision: self.changed_at,
                 },
                 value: value.clone(),
@@ -1096,3 +1569,94 @@ where
         None
     }
 }
+
+/// The persisted form of a `Memo`: its inputs are stored as encoded
+/// database-keys (`None` meaning `MemoInputs::Constant`).
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SavedMemo<V> {
+    value: Option<V>,
+    fingerprint: Option<u64>,
+    verified_at: Revision,
+    changed_at: Revision,
+    durability: Durability,
+    inputs: Option<Vec<Vec<u8>>>,
+}
+
+#[cfg(feature = "serde")]
+impl<DB, Q, MP> PersistentQueryStorageOps<DB> for DerivedStorage<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    Q::Key: Serialize + DeserializeOwned,
+    Q::Value: Serialize + DeserializeOwned,
+    DB: Database,
+    DB::DatabaseKey: PersistentDatabaseKey<DB>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn save(&self, _db: &DB) -> io::Result<Vec<u8>> {
+        let map = self.map.read();
+        let mut entries = Vec::with_capacity(map.len());
+        for (key, query_state) in map.iter() {
+            let memo = match query_state {
+                QueryState::Memoized(memo) => memo,
+                QueryState::InProgress { .. } => continue,
+            };
+
+            let inputs = match &memo.inputs {
+                MemoInputs::Constant => None,
+                MemoInputs::Tracked { inputs } => Some(
+                    inputs
+                        .iter()
+                        .map(|input| input.encode())
+                        .collect::<io::Result<Vec<_>>>()?,
+                ),
+
+                // Untracked inputs have to be re-executed in every
+                // revision anyway.
+                MemoInputs::Untracked => continue,
+            };
+
+            entries.push((
+                key,
+                SavedMemo {
+                    value: memo.value.as_ref(),
+                    fingerprint: memo.fingerprint,
+                    verified_at: memo.verified_at,
+                    changed_at: memo.changed_at,
+                    durability: memo.durability,
+                    inputs,
+                },
+            ));
+        }
+        persist::encode(&entries)
+    }
+
+    fn load(&self, _db: &DB, bytes: &[u8]) -> io::Result<()> {
+        let entries: Vec<(Q::Key, SavedMemo<Q::Value>)> = persist::decode(bytes)?;
+        let mut map_write = self.map.write();
+        for (key, saved) in entries {
+            let inputs = match saved.inputs {
+                None => MemoInputs::Constant,
+                Some(inputs) => MemoInputs::Tracked {
+                    inputs: Arc::new(
+                        inputs
+                            .iter()
+                            .map(|bytes| DB::DatabaseKey::decode(bytes))
+                            .collect::<io::Result<_>>()?,
+                    ),
+                },
+            };
+
+            let memo = Memo {
+                value: saved.value,
+                fingerprint: saved.fingerprint,
+                verified_at: saved.verified_at,
+                changed_at: saved.changed_at,
+                durability: saved.durability,
+                inputs,
+            };
+            map_write.insert(key, QueryState::Memoized(memo));
+        }
+        Ok(())
+    }
+}
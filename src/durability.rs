@@ -0,0 +1,37 @@
+/// Describes how likely a value is to change -- how "durable" it is.
+/// By default, inputs have `Durability::Low`. But when you have
+/// inputs that change rarely -- such as the sources of the standard
+/// library -- you can give them `Durability::High` using [the
+/// `set_with_durability` method].
+///
+/// Derived queries take on the lowest durability of their inputs.
+/// Salsa tracks the last revision in which an input of each durability
+/// changed; a derived value whose durability is higher than that of
+/// every input changed since it was last verified can be re-used
+/// without walking its inputs at all.
+///
+/// [the `set_with_durability` method]: struct.QueryTableMut.html#method.set_with_durability
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Durability {
+    /// Low durability: the value is expected to change frequently
+    /// (e.g., the contents of files being edited). This is the
+    /// durability used by `set`.
+    Low,
+
+    /// Medium durability: the value changes occasionally.
+    Medium,
+
+    /// High durability: the value is expected to change rarely, if
+    /// ever (e.g., the sources of the standard library).
+    High,
+}
+
+impl Durability {
+    /// The number of durability levels.
+    pub(crate) const LEN: usize = 3;
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
@@ -1,20 +1,31 @@
+use crate::debug::DependencyGraph;
 use crate::debug::TableEntry;
-use crate::plumbing::CycleDetected;
+#[cfg(feature = "serde")]
+use crate::persist::{self, PersistentQueryStorageOps};
+use crate::plumbing::HasQueryGroup;
 use crate::plumbing::InputQueryStorageOps;
 use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
 use crate::runtime::ChangedAt;
 use crate::runtime::Revision;
 use crate::runtime::StampedValue;
+use crate::Cycle;
 use crate::Database;
+use crate::Durability;
 use crate::Event;
 use crate::EventKind;
 use crate::Query;
+use crate::QueryMemoryUsage;
 use crate::SweepStrategy;
 use log::debug;
 use parking_lot::RwLock;
 use rustc_hash::FxHashMap;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::hash_map::Entry;
+#[cfg(feature = "serde")]
+use std::io;
+use std::mem::size_of;
 
 /// Input queries store the result plus a list of the other queries
 /// that they invoked. This means we can avoid recomputing them when
@@ -25,6 +36,10 @@ where
     DB: Database,
 {
     map: RwLock<FxHashMap<Q::Key, StampedValue<Q::Value>>>,
+
+    /// For each key that was removed (and not set since), when it was
+    /// removed; values that observed the key as absent depend on this.
+    removed: RwLock<FxHashMap<Q::Key, ChangedAt>>,
 }
 
 impl<DB, Q> std::panic::RefUnwindSafe for InputStorage<DB, Q>
@@ -44,6 +59,7 @@ where
     fn default() -> Self {
         InputStorage {
             map: RwLock::new(FxHashMap::default()),
+            removed: RwLock::new(FxHashMap::default()),
         }
     }
 }
@@ -60,7 +76,7 @@ where
         _db: &'q DB,
         key: &Q::Key,
         _database_key: &DB::DatabaseKey,
-    ) -> Result<StampedValue<Q::Value>, CycleDetected> {
+    ) -> Result<StampedValue<Q::Value>, Cycle<DB>> {
         {
             let map_read = self.map.read();
             if let Some(value) = map_read.get(key) {
@@ -71,12 +87,28 @@ where
         panic!("no value set for {:?}({:?})", Q::default(), key)
     }
 
+    /// When the value for `key` last changed, including its removal.
+    /// Keys that were never set are treated as having been absent
+    /// since the first revision.
+    fn changed_at(&self, key: &Q::Key) -> ChangedAt {
+        if let Some(value) = self.map.read().get(key) {
+            return value.changed_at;
+        }
+
+        self.removed.read().get(key).cloned().unwrap_or(ChangedAt {
+            is_constant: false,
+            durability: Durability::Low,
+            revision: Revision::ZERO,
+        })
+    }
+
     fn set_common(
         &self,
         db: &DB,
         key: &Q::Key,
         database_key: &DB::DatabaseKey,
         value: Q::Value,
+        durability: Durability,
         is_constant: IsConstant,
     ) {
         let key = key.clone();
@@ -105,11 +137,22 @@ where
             // into the same cell while we block on the lock.)
             let changed_at = ChangedAt {
                 is_constant: is_constant.0,
+                durability,
                 revision: next_revision,
             };
 
             let stamped_value = StampedValue { value, changed_at };
 
+            // If the old value was more durable than the new one, then
+            // values derived from it may have assumed that durability:
+            // record the change under the old durability, too.
+            let changed_durability = match map.get(&key) {
+                Some(old_value) => durability.max(old_value.changed_at.durability),
+                None => durability,
+            };
+
+            self.removed.write().remove(&key);
+
             match map.entry(key) {
                 Entry::Occupied(mut entry) => {
                     assert!(
@@ -128,6 +171,8 @@ where
                     entry.insert(stamped_value);
                 }
             }
+
+            changed_durability
         });
     }
 }
@@ -142,7 +187,7 @@ where
         db: &DB,
         key: &Q::Key,
         database_key: &DB::DatabaseKey,
-    ) -> Result<Q::Value, CycleDetected> {
+    ) -> Result<Q::Value, Cycle<DB>> {
         let StampedValue { value, changed_at } = self.read(db, key, &database_key)?;
 
         db.salsa_runtime()
@@ -165,16 +210,7 @@ where
             revision,
         );
 
-        let changed_at = {
-            let map_read = self.map.read();
-            map_read
-                .get(key)
-                .map(|v| v.changed_at)
-                .unwrap_or(ChangedAt {
-                    is_constant: false,
-                    revision: Revision::ZERO,
-                })
-        };
+        let changed_at = self.changed_at(key);
 
         debug!(
             "{:?}({:?}): changed_at = {:?}",
@@ -205,6 +241,16 @@ where
             })
             .collect()
     }
+
+    fn dependency_graph(&self, db: &DB, graph: &mut DependencyGraph<DB>)
+    where
+        DB: HasQueryGroup<Q::Group>,
+    {
+        let map_read = self.map.read();
+        for (key, stamped_value) in map_read.iter() {
+            graph.add_node::<Q>(db, key, None, stamped_value.changed_at.revision, false);
+        }
+    }
 }
 
 impl<DB, Q> QueryStorageMassOps<DB> for InputStorage<DB, Q>
@@ -213,6 +259,20 @@ where
     DB: Database,
 {
     fn sweep(&self, _db: &DB, _strategy: SweepStrategy) {}
+
+    fn memory_usage(&self, _db: &DB) -> Option<QueryMemoryUsage> {
+        let map_read = self.map.read();
+        let mut usage = QueryMemoryUsage {
+            query: format!("{:?}", Q::default()),
+            entries: map_read.len(),
+            ..QueryMemoryUsage::default()
+        };
+        for (key, stamped_value) in map_read.iter() {
+            usage.key_bytes += size_of::<Q::Key>() + Q::key_heap_size(key);
+            usage.value_bytes += size_of::<Q::Value>() + Q::value_heap_size(&stamped_value.value);
+        }
+        Some(usage)
+    }
 }
 
 impl<DB, Q> InputQueryStorageOps<DB, Q> for InputStorage<DB, Q>
@@ -220,15 +280,139 @@ where
     Q: Query<DB>,
     DB: Database,
 {
-    fn set(&self, db: &DB, key: &Q::Key, database_key: &DB::DatabaseKey, value: Q::Value) {
-        log::debug!("{:?}({:?}) = {:?}", Q::default(), key, value);
+    fn set(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        value: Q::Value,
+        durability: Durability,
+    ) {
+        log::debug!(
+            "{:?}({:?}) = {:?} ({:?})",
+            Q::default(),
+            key,
+            value,
+            durability
+        );
 
-        self.set_common(db, key, database_key, value, IsConstant(false))
+        self.set_common(db, key, database_key, value, durability, IsConstant(false))
     }
 
     fn set_constant(&self, db: &DB, key: &Q::Key, database_key: &DB::DatabaseKey, value: Q::Value) {
         log::debug!("{:?}({:?}) = {:?}", Q::default(), key, value);
 
-        self.set_common(db, key, database_key, value, IsConstant(true))
+        self.set_common(
+            db,
+            key,
+            database_key,
+            value,
+            Durability::High,
+            IsConstant(true),
+        )
+    }
+
+    fn set_if_changed(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        value: Q::Value,
+        durability: Durability,
+    ) where
+        Q::Value: Eq,
+    {
+        // Only the database with `&mut` access sets inputs, so the
+        // value cannot change between this check and `set`.
+        if let Some(old_value) = self.map.read().get(key) {
+            if old_value.value == value && old_value.changed_at.durability == durability {
+                log::debug!("{:?}({:?}) unchanged", Q::default(), key);
+                return;
+            }
+        }
+
+        self.set(db, key, database_key, value, durability)
+    }
+
+    fn remove(&self, db: &DB, key: &Q::Key, database_key: &DB::DatabaseKey) {
+        log::debug!("{:?}({:?}) removed", Q::default(), key);
+
+        // Removing a key that has no value changes nothing, so it
+        // does not need a new revision.
+        if !self.map.read().contains_key(key) {
+            return;
+        }
+
+        // See `set_common` regarding the locking order.
+        db.salsa_runtime()
+            .with_incremented_revision(|next_revision| {
+                let mut map = self.map.write();
+
+                db.salsa_event(|| Event {
+                    runtime_id: db.salsa_runtime().id(),
+                    kind: EventKind::WillChangeInputValue {
+                        database_key: database_key.clone(),
+                    },
+                });
+
+                let old_value = map.remove(key).unwrap();
+                assert!(
+                    !old_value.changed_at.is_constant,
+                    "removing `{:?}({:?})`, which was marked as constant",
+                    Q::default(),
+                    key,
+                );
+
+                let durability = old_value.changed_at.durability;
+                self.removed.write().insert(
+                    key.clone(),
+                    ChangedAt {
+                        is_constant: false,
+                        durability,
+                        revision: next_revision,
+                    },
+                );
+
+                durability
+            });
+    }
+
+    fn try_fetch_if_set(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+    ) -> Option<Q::Value> {
+        let value = self.map.read().get(key).cloned();
+        let (value, changed_at) = match value {
+            Some(StampedValue { value, changed_at }) => (Some(value), changed_at),
+            None => (None, self.changed_at(key)),
+        };
+
+        db.salsa_runtime()
+            .report_query_read(database_key, changed_at);
+
+        value
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<DB, Q> PersistentQueryStorageOps<DB> for InputStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Key: Serialize + DeserializeOwned,
+    Q::Value: Serialize + DeserializeOwned,
+    DB: Database,
+{
+    fn save(&self, _db: &DB) -> io::Result<Vec<u8>> {
+        let map = self.map.read();
+        let entries: Vec<_> = map.iter().collect();
+        persist::encode(&entries)
+    }
+
+    fn load(&self, _db: &DB, bytes: &[u8]) -> io::Result<()> {
+        let entries: Vec<(Q::Key, StampedValue<Q::Value>)> = persist::decode(bytes)?;
+        self.map.write().extend(entries);
+        Ok(())
     }
 }
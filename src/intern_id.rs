@@ -0,0 +1,110 @@
+use std::fmt;
+use std::num::NonZeroU32;
+
+/// The id assigned to a value by an interned query (see
+/// `#[salsa::interned]`) -- basically a newtype'd `u32`. Typically, it
+/// is wrapped in a key type of your own devising which implements
+/// `InternKey`.
+///
+/// `InternId` values can be converted to and from `u32` and `usize`
+/// using the `From` impls:
+///
+/// ```
+/// # use salsa::InternId;
+/// let intern_id = InternId::from(22_u32);
+/// assert_eq!(intern_id, InternId::from(22_usize));
+/// assert_eq!(u32::from(intern_id), 22);
+/// ```
+///
+/// Values greater than or equal to `InternId::MAX` are reserved (so
+/// that, for example, `Option<InternId>` takes no more space than an
+/// `InternId`) and converting them panics:
+///
+/// ```should_panic
+/// # use salsa::InternId;
+/// InternId::from(InternId::MAX);
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InternId {
+    value: NonZeroU32,
+}
+
+impl InternId {
+    /// The maximum allowed `InternId`. This value can grow between
+    /// releases without affecting semver.
+    pub const MAX: u32 = 0xFFFF_FF00;
+
+    /// Convert this raw-id into a u32 value.
+    pub fn as_u32(self) -> u32 {
+        self.value.get() - 1
+    }
+
+    /// Convert this raw-id into a usize value.
+    pub fn as_usize(self) -> usize {
+        self.as_u32() as usize
+    }
+}
+
+impl From<InternId> for u32 {
+    fn from(raw: InternId) -> u32 {
+        raw.as_u32()
+    }
+}
+
+impl From<InternId> for usize {
+    fn from(raw: InternId) -> usize {
+        raw.as_usize()
+    }
+}
+
+impl From<u32> for InternId {
+    fn from(id: u32) -> InternId {
+        assert!(id < InternId::MAX);
+        InternId {
+            value: NonZeroU32::new(id + 1).unwrap(),
+        }
+    }
+}
+
+impl From<usize> for InternId {
+    fn from(id: usize) -> InternId {
+        assert!(id < (InternId::MAX as usize));
+        InternId {
+            value: NonZeroU32::new((id + 1) as u32).unwrap(),
+        }
+    }
+}
+
+impl fmt::Debug for InternId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_usize().fmt(f)
+    }
+}
+
+impl fmt::Display for InternId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_usize().fmt(f)
+    }
+}
+
+/// Trait implemented for the value type of interned queries: the
+/// value is a key that represents the interned data, and it must be
+/// convertible to and from the `InternId` allocated for that data.
+pub trait InternKey {
+    /// Create an instance of the intern-key from a `InternId` value.
+    fn from_intern_id(v: InternId) -> Self;
+
+    /// Extract the `InternId` with which the intern-key was created.
+    fn as_intern_id(&self) -> InternId;
+}
+
+impl InternKey for InternId {
+    fn from_intern_id(v: InternId) -> InternId {
+        v
+    }
+
+    fn as_intern_id(&self) -> InternId {
+        *self
+    }
+}
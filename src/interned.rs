@@ -0,0 +1,385 @@
+use crate::debug::DependencyGraph;
+use crate::debug::TableEntry;
+use crate::intern_id::InternId;
+#[cfg(feature = "serde")]
+use crate::persist::{self, PersistentQueryStorageOps};
+use crate::plumbing::HasQueryGroup;
+use crate::plumbing::QueryStorageMassOps;
+use crate::plumbing::QueryStorageOps;
+use crate::runtime::ChangedAt;
+use crate::runtime::Revision;
+use crate::Cycle;
+use crate::Database;
+use crate::Durability;
+use crate::InternKey;
+use crate::Query;
+use crate::QueryMemoryUsage;
+use crate::SweepStrategy;
+use log::debug;
+use parking_lot::RwLock;
+use rustc_hash::FxHashMap;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::hash_map::Entry;
+#[cfg(feature = "serde")]
+use std::io;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+/// Handles storage where the value is an id allocated for the key:
+/// each distinct key is assigned a fresh `InternId` the first time it
+/// is interned, and always maps to that id afterwards.
+pub struct InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    tables: RwLock<InternTables<Q::Key>>,
+}
+
+/// Storage for looking up the key of an interned value; the data
+/// itself lives in the `InternedStorage` of the interned query `IQ`.
+pub struct LookupInternedStorage<DB, Q, IQ> {
+    phantom: PhantomData<fn(DB, Q, IQ)>,
+}
+
+struct InternTables<K> {
+    /// Map from the key to the corresponding intern-index.
+    map: FxHashMap<K, InternId>,
+
+    /// For each valid intern-index, stores the interned key and the
+    /// revision in which it was interned.
+    values: Vec<InternedSlot<K>>,
+}
+
+struct InternedSlot<K> {
+    key: K,
+    interned_at: Revision,
+}
+
+impl<DB, Q> std::panic::RefUnwindSafe for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database,
+    Q::Key: std::panic::RefUnwindSafe,
+    Q::Value: InternKey,
+    Q::Value: std::panic::RefUnwindSafe,
+{
+}
+
+impl<DB, Q> Default for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    fn default() -> Self {
+        InternedStorage {
+            tables: RwLock::new(InternTables {
+                map: FxHashMap::default(),
+                values: vec![],
+            }),
+        }
+    }
+}
+
+impl<DB, Q, IQ> Default for LookupInternedStorage<DB, Q, IQ> {
+    fn default() -> Self {
+        LookupInternedStorage {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<K> InternTables<K> {
+    fn slot(&self, index: InternId) -> &InternedSlot<K> {
+        match self.values.get(index.as_usize()) {
+            Some(slot) => slot,
+            None => panic!("no value interned with id {:?}", index),
+        }
+    }
+}
+
+impl<DB, Q> InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    fn intern_index(&self, db: &DB, key: &Q::Key) -> (InternId, Revision) {
+        {
+            let tables = self.tables.read();
+            if let Some(&index) = tables.map.get(key) {
+                return (index, tables.slot(index).interned_at);
+            }
+        }
+
+        let revision_now = db.salsa_runtime().current_revision();
+        let mut tables = self.tables.write();
+        let tables = &mut *tables;
+
+        // Somebody else may have interned the key while we were
+        // waiting for the write lock.
+        match tables.map.entry(key.clone()) {
+            Entry::Occupied(entry) => {
+                let index = *entry.get();
+                (index, tables.values[index.as_usize()].interned_at)
+            }
+
+            Entry::Vacant(entry) => {
+                let index = InternId::from(tables.values.len());
+                debug!("{:?}({:?}) interned as {:?}", Q::default(), key, index);
+                tables.values.push(InternedSlot {
+                    key: key.clone(),
+                    interned_at: revision_now,
+                });
+                entry.insert(index);
+                (index, revision_now)
+            }
+        }
+    }
+}
+
+/// The mapping between a key and its id never changes once it is
+/// created, so reads of interned queries are reported as constant.
+fn interned_changed_at(interned_at: Revision) -> ChangedAt {
+    ChangedAt {
+        is_constant: true,
+        durability: Durability::High,
+        revision: interned_at,
+    }
+}
+
+impl<DB, Q> QueryStorageOps<DB, Q> for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    fn try_fetch(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+    ) -> Result<Q::Value, Cycle<DB>> {
+        let (index, interned_at) = self.intern_index(db, key);
+
+        db.salsa_runtime()
+            .report_query_read(database_key, interned_changed_at(interned_at));
+
+        Ok(<Q::Value>::from_intern_id(index))
+    }
+
+    fn maybe_changed_since(
+        &self,
+        _db: &DB,
+        revision: Revision,
+        key: &Q::Key,
+        _database_key: &DB::DatabaseKey,
+    ) -> bool {
+        let tables = self.tables.read();
+        match tables.map.get(key) {
+            Some(&index) => tables.slot(index).interned_at > revision,
+            None => true,
+        }
+    }
+
+    fn is_constant(&self, _db: &DB, _key: &Q::Key) -> bool {
+        true
+    }
+
+    fn entries<C>(&self, _db: &DB) -> C
+    where
+        C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
+    {
+        let tables = self.tables.read();
+        tables
+            .map
+            .iter()
+            .map(|(key, &index)| {
+                TableEntry::new(key.clone(), Some(<Q::Value>::from_intern_id(index)))
+            })
+            .collect()
+    }
+
+    fn dependency_graph(&self, db: &DB, graph: &mut DependencyGraph<DB>)
+    where
+        DB: HasQueryGroup<Q::Group>,
+    {
+        let tables = self.tables.read();
+        for slot in &tables.values {
+            graph.add_node::<Q>(db, &slot.key, None, slot.interned_at, false);
+        }
+    }
+}
+
+impl<DB, Q> QueryStorageMassOps<DB> for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    // Interned values are never collected: an id that was handed out
+    // may be stored anywhere, so it has to remain valid.
+    fn sweep(&self, _db: &DB, _strategy: SweepStrategy) {}
+
+    fn memory_usage(&self, _db: &DB) -> Option<QueryMemoryUsage> {
+        let tables = self.tables.read();
+        let mut usage = QueryMemoryUsage {
+            query: format!("{:?}", Q::default()),
+            entries: tables.values.len(),
+            ..QueryMemoryUsage::default()
+        };
+        // Each key is stored twice: in `map` and in `values`.
+        for slot in &tables.values {
+            usage.key_bytes += 2 * (size_of::<Q::Key>() + Q::key_heap_size(&slot.key));
+            usage.value_bytes += size_of::<InternId>();
+        }
+        Some(usage)
+    }
+}
+
+impl<DB, Q, IQ> LookupInternedStorage<DB, Q, IQ>
+where
+    Q: Query<DB>,
+    Q::Key: InternKey,
+    IQ: Query<DB, Key = Q::Value, Value = Q::Key, Storage = InternedStorage<DB, IQ>>,
+    DB: Database + HasQueryGroup<IQ::Group>,
+{
+    fn interned_storage(db: &DB) -> &InternedStorage<DB, IQ> {
+        let group_storage = <DB as HasQueryGroup<IQ::Group>>::group_storage(db);
+        IQ::query_storage(group_storage)
+    }
+}
+
+impl<DB, Q, IQ> QueryStorageOps<DB, Q> for LookupInternedStorage<DB, Q, IQ>
+where
+    Q: Query<DB>,
+    Q::Key: InternKey,
+    IQ: Query<DB, Key = Q::Value, Value = Q::Key, Storage = InternedStorage<DB, IQ>>,
+    DB: Database + HasQueryGroup<IQ::Group>,
+{
+    fn try_fetch(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+    ) -> Result<Q::Value, Cycle<DB>> {
+        let index = key.as_intern_id();
+        let (value, interned_at) = {
+            let tables = Self::interned_storage(db).tables.read();
+            let slot = tables.slot(index);
+            (slot.key.clone(), slot.interned_at)
+        };
+
+        db.salsa_runtime()
+            .report_query_read(database_key, interned_changed_at(interned_at));
+
+        Ok(value)
+    }
+
+    fn maybe_changed_since(
+        &self,
+        db: &DB,
+        revision: Revision,
+        key: &Q::Key,
+        _database_key: &DB::DatabaseKey,
+    ) -> bool {
+        let index = key.as_intern_id();
+        let tables = Self::interned_storage(db).tables.read();
+        match tables.values.get(index.as_usize()) {
+            Some(slot) => slot.interned_at > revision,
+            None => true,
+        }
+    }
+
+    fn is_constant(&self, _db: &DB, _key: &Q::Key) -> bool {
+        true
+    }
+
+    fn entries<C>(&self, db: &DB) -> C
+    where
+        C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
+    {
+        let tables = Self::interned_storage(db).tables.read();
+        tables
+            .values
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| {
+                TableEntry::new(
+                    <Q::Key>::from_intern_id(InternId::from(index)),
+                    Some(slot.key.clone()),
+                )
+            })
+            .collect()
+    }
+
+    // The entries are those of the interned query.
+    fn dependency_graph(&self, _db: &DB, _graph: &mut DependencyGraph<DB>)
+    where
+        DB: HasQueryGroup<Q::Group>,
+    {
+    }
+}
+
+impl<DB, Q, IQ> QueryStorageMassOps<DB> for LookupInternedStorage<DB, Q, IQ>
+where
+    DB: Database,
+{
+    fn sweep(&self, _db: &DB, _strategy: SweepStrategy) {}
+
+    fn memory_usage(&self, _db: &DB) -> Option<QueryMemoryUsage> {
+        None
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<DB, Q> PersistentQueryStorageOps<DB> for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Key: Serialize + DeserializeOwned,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    fn save(&self, _db: &DB) -> io::Result<Vec<u8>> {
+        let tables = self.tables.read();
+        let slots: Vec<_> = tables
+            .values
+            .iter()
+            .map(|slot| (&slot.key, slot.interned_at))
+            .collect();
+        persist::encode(&slots)
+    }
+
+    /// Replaces the interned keys, as the saved memos may contain the
+    /// ids that were assigned to them.
+    fn load(&self, _db: &DB, bytes: &[u8]) -> io::Result<()> {
+        let slots: Vec<(Q::Key, Revision)> = persist::decode(bytes)?;
+        let mut tables = self.tables.write();
+        tables.map.clear();
+        tables.values.clear();
+        for (key, interned_at) in slots {
+            let index = InternId::from(tables.values.len());
+            tables.map.insert(key.clone(), index);
+            tables.values.push(InternedSlot { key, interned_at });
+        }
+        Ok(())
+    }
+}
+
+/// The lookup query has no entries of its own.
+#[cfg(feature = "serde")]
+impl<DB, Q, IQ> PersistentQueryStorageOps<DB> for LookupInternedStorage<DB, Q, IQ>
+where
+    DB: Database,
+{
+    fn save(&self, _db: &DB) -> io::Result<Vec<u8>> {
+        Ok(vec![])
+    }
+
+    fn load(&self, _db: &DB, _bytes: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -9,8 +9,16 @@
 //! from previous invocations as appropriate.
 
 mod derived;
+mod durability;
 mod input;
+mod intern_id;
+mod interned;
+mod lru;
+mod memory;
+#[cfg(feature = "serde")]
+mod persist;
 mod runtime;
+mod stats;
 
 pub mod debug;
 /// Items in this module are public for implementation reasons,
@@ -18,16 +26,30 @@ pub mod debug;
 #[doc(hidden)]
 pub mod plumbing;
 
-use crate::plumbing::CycleDetected;
 use crate::plumbing::InputQueryStorageOps;
+use crate::plumbing::LruQueryStorageOps;
 use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
 use derive_new::new;
+use parking_lot::Mutex;
 use std::fmt::{self, Debug};
+use std::future::Future;
 use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
+pub use crate::durability::Durability;
+pub use crate::intern_id::InternId;
+pub use crate::intern_id::InternKey;
+pub use crate::memory::HeapSize;
+pub use crate::memory::QueryMemoryUsage;
+pub use crate::runtime::Priority;
 pub use crate::runtime::Runtime;
 pub use crate::runtime::RuntimeId;
+pub use crate::runtime::SnapshotInfo;
+pub use crate::stats::QueryStats;
 
 /// The base trait which your "query context" must implement. Gives
 /// access to the salsa runtime, which you must embed into your query
@@ -97,6 +119,43 @@ pub trait Database: plumbing::DatabaseStorageTypes + plumbing::DatabaseOps {
         <Self as plumbing::GetQueryTable<Q>>::get_query_table_mut(self)
     }
 
+    /// Invokes `op` to make a batch of changes to inputs, all of which
+    /// are applied in a single new revision: the global query write
+    /// lock is acquired (see [the `query_mut` method]) and the
+    /// revision incremented only once, rather than once per change
+    /// (and not at all if `op` changes nothing, e.g. because it only
+    /// sets inputs to equal values with `set_if_changed`). A
+    /// `WillChangeInputValue` event is still reported for each change.
+    /// Transactions cannot be nested.
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    fn transaction(&mut self, op: impl FnOnce(&Transaction<'_, Self>)) {
+        let db = &*self;
+        db.salsa_runtime().transaction(|| op(&Transaction { db }));
+    }
+
+    /// Returns the statistics collected about each derived query,
+    /// those with the largest total execution time first. Empty unless
+    /// the collection was enabled with
+    /// `Runtime::set_query_stats_enabled`.
+    fn query_stats(&self) -> Vec<QueryStats> {
+        self.salsa_runtime().query_stats().report()
+    }
+
+    /// Returns the memory used by each query, those that use the most
+    /// bytes first. Heap memory owned by keys and values is only
+    /// included for queries with `#[salsa::heap_size]`.
+    fn memory_report(&self) -> Vec<QueryMemoryUsage> {
+        let mut report = vec![];
+        self.for_each_query(|query_storage| report.extend(query_storage.memory_usage(self)));
+        report.sort_by(|a, b| {
+            let total =
+                |usage: &QueryMemoryUsage| usage.key_bytes + usage.value_bytes + usage.inputs_bytes;
+            total(b).cmp(&total(a)).then_with(|| a.query.cmp(&b.query))
+        });
+        report
+    }
+
     /// This function is invoked at key points in the salsa
     /// runtime. It permits the database to be customized and to
     /// inject logging or other custom behavior.
@@ -109,6 +168,34 @@ pub trait Database: plumbing::DatabaseStorageTypes + plumbing::DatabaseOps {
     fn on_propagated_panic(&self) -> ! {
         panic!("concurrent salsa query panicked")
     }
+
+    /// Writes the values of all inputs and the memoized values of all
+    /// derived queries, together with the dependencies of those
+    /// values, to `writer`. Requires the `serde` feature and that the
+    /// keys and values of all queries implement `Serialize` and
+    /// `Deserialize`.
+    #[cfg(feature = "serde")]
+    fn save_to(&self, writer: impl std::io::Write) -> std::io::Result<()>
+    where
+        Self::DatabaseStorage: plumbing::PersistentDatabaseOps<Self>,
+    {
+        persist::save_to(self, writer)
+    }
+
+    /// Restores the values written by `save_to`. The restored memos
+    /// are not re-executed as long as the inputs they depend on are
+    /// not changed afterwards; they are revalidated like memos
+    /// computed by this database.
+    ///
+    /// Must be invoked before any input of this database is set (and
+    /// thus typically right after creating it).
+    #[cfg(feature = "serde")]
+    fn load_from(&mut self, reader: impl std::io::Read) -> std::io::Result<()>
+    where
+        Self::DatabaseStorage: plumbing::PersistentDatabaseOps<Self>,
+    {
+        persist::load_from(self, reader)
+    }
 }
 
 /// The `Event` struct identifies various notable things that can
@@ -174,6 +261,9 @@ pub enum EventKind<DB: Database> {
     WillExecute {
         /// The database-key for the affected value. Implements `Debug`.
         database_key: DB::DatabaseKey,
+
+        /// Why the memoized value (if any) could not be reused.
+        reason: ExecuteReason<DB>,
     },
 }
 
@@ -196,9 +286,65 @@ impl<DB: Database> fmt::Debug for EventKind<DB> {
                 .debug_struct("WillChangeInputValue")
                 .field("database_key", database_key)
                 .finish(),
-            EventKind::WillExecute { database_key } => fmt
+            EventKind::WillExecute {
+                database_key,
+                reason,
+            } => fmt
                 .debug_struct("WillExecute")
                 .field("database_key", database_key)
+                .field("reason", reason)
+                .finish(),
+        }
+    }
+}
+
+/// Explains why the function of a query is executed; see
+/// `EventKind::WillExecute`.
+pub enum ExecuteReason<DB: Database> {
+    /// The query was never executed for this key before (or its memo
+    /// was discarded by a sweep).
+    NotMemoized,
+
+    /// The query was executed before, but its value was not retained
+    /// (e.g., because it was evicted by the LRU, or because the query
+    /// uses `#[salsa::dependencies]` or `#[salsa::hashed]`).
+    NoMemoizedValue,
+
+    /// The previous execution had untracked inputs (e.g., it was
+    /// volatile), so its value cannot be validated.
+    UntrackedInputs,
+
+    /// An input of the previous execution may have changed since the
+    /// memoized value was last verified. This is the first such input
+    /// that was found; other inputs may have changed as well.
+    InputChanged {
+        /// The database-key of the input. Implements `Debug`.
+        database_key: DB::DatabaseKey,
+    },
+}
+
+impl<DB: Database> Clone for ExecuteReason<DB> {
+    fn clone(&self) -> Self {
+        match self {
+            ExecuteReason::NotMemoized => ExecuteReason::NotMemoized,
+            ExecuteReason::NoMemoizedValue => ExecuteReason::NoMemoizedValue,
+            ExecuteReason::UntrackedInputs => ExecuteReason::UntrackedInputs,
+            ExecuteReason::InputChanged { database_key } => ExecuteReason::InputChanged {
+                database_key: database_key.clone(),
+            },
+        }
+    }
+}
+
+impl<DB: Database> fmt::Debug for ExecuteReason<DB> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteReason::NotMemoized => fmt.debug_struct("NotMemoized").finish(),
+            ExecuteReason::NoMemoizedValue => fmt.debug_struct("NoMemoizedValue").finish(),
+            ExecuteReason::UntrackedInputs => fmt.debug_struct("UntrackedInputs").finish(),
+            ExecuteReason::InputChanged { database_key } => fmt
+                .debug_struct("InputChanged")
+                .field("database_key", database_key)
                 .finish(),
         }
     }
@@ -318,7 +464,9 @@ pub trait ParallelDatabase: Database + Send {
     /// # Panics
     ///
     /// It is not permitted to create a snapshot from inside of a
-    /// query. Attepting to do so will panic.
+    /// query. Attepting to do so will panic. Use [`fork`] instead.
+    ///
+    /// [`fork`]: trait.ParallelDatabase.html#method.fork
     ///
     /// # Deadlock warning
     ///
@@ -357,6 +505,109 @@ pub trait ParallelDatabase: Database + Send {
     /// }
     /// ```
     fn snapshot(&self) -> Snapshot<Self>;
+
+    /// Like `snapshot`, but for use from inside of a query, to execute
+    /// some of its work in parallel: the reads of the queries invoked
+    /// on the fork are recorded as reads of the query that created it,
+    /// so that its dependencies remain precise. (Outside of a query,
+    /// the same holds for forks of a fork; otherwise, this is
+    /// equivalent to `snapshot`.)
+    ///
+    /// The fork shares the read lock of the query that created it.
+    /// Queries invoked on the fork must not (transitively) require
+    /// that query: that is reported as a cycle.
+    ///
+    /// # Panics
+    ///
+    /// The query that created the fork panics if the fork (or a fork
+    /// of it) is still alive when the query completes.
+    fn fork(&self) -> Snapshot<Self> {
+        self.salsa_runtime().forking(|| self.snapshot())
+    }
+
+    /// Invokes `op` for each of `keys`, in parallel, and returns the
+    /// results in the order of the keys. The work is distributed
+    /// across a fixed pool of worker threads (one per available CPU,
+    /// at most), each of which uses its own snapshot of the database.
+    ///
+    /// If `op` panics for some key, the remaining keys are skipped;
+    /// once all workers are done, this panics with `Canceled` if the
+    /// current revision was canceled in the meantime, and invokes
+    /// `on_propagated_panic` otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Like `snapshot`, this cannot be invoked from inside of a query.
+    fn par_map<K, R>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        op: impl Fn(&Self, K) -> R + Sync,
+    ) -> Vec<R>
+    where
+        K: Send,
+        R: Send,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let len = keys.len();
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(len);
+        let snapshots: Vec<Snapshot<Self>> = (0..workers).map(|_| self.snapshot()).collect();
+
+        let keys = Mutex::new(keys.into_iter().enumerate());
+        let panicked = AtomicBool::new(false);
+        let results = std::thread::scope(|scope| {
+            let handles: Vec<_> = snapshots
+                .into_iter()
+                .map(|db| {
+                    let (keys, panicked, op) = (&keys, &panicked, &op);
+                    scope.spawn(move || {
+                        let mut results = Vec::new();
+                        let guard = PanicFlag(panicked);
+                        while !panicked.load(Ordering::Relaxed) {
+                            let next = keys.lock().next();
+                            match next {
+                                Some((index, key)) => results.push((index, op(&db, key))),
+                                None => break,
+                            }
+                        }
+                        std::mem::forget(guard);
+                        results
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join())
+                .collect::<Vec<_>>()
+        });
+
+        let mut ordered: Vec<Option<R>> = (0..len).map(|_| None).collect();
+        for worker_results in results {
+            match worker_results {
+                Ok(worker_results) => {
+                    for (index, result) in worker_results {
+                        ordered[index] = Some(result);
+                    }
+                }
+                Err(_) => {
+                    self.salsa_runtime().unwind_if_canceled();
+                    self.on_propagated_panic()
+                }
+            }
+        }
+        ordered.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+/// Set while a worker of `ParallelDatabase::par_map` unwinds, to stop
+/// the other workers early.
+struct PanicFlag<'me>(&'me AtomicBool);
+
+impl Drop for PanicFlag<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
 }
 
 /// Simple wrapper struct that takes ownership of a database `DB` and
@@ -382,6 +633,21 @@ where
     pub fn new(db: DB) -> Self {
         Snapshot { db }
     }
+
+    /// Invokes `op` with the database, returning `Err(Canceled)` if
+    /// it was canceled (that is, if it unwound with `Canceled` as the
+    /// panic payload). Other panics are propagated.
+    pub fn catch_canceled<T>(&self, op: impl FnOnce(&DB) -> T) -> Result<T, Canceled> {
+        // Salsa is panic-safe: a query that unwinds leaves no trace in
+        // the database, so it is fine to keep using it afterwards.
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| op(&self.db))) {
+            Ok(value) => Ok(value),
+            Err(payload) => match payload.downcast::<Canceled>() {
+                Ok(_) => Err(Canceled),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        }
+    }
 }
 
 impl<DB> std::ops::Deref for Snapshot<DB>
@@ -395,6 +661,105 @@ where
     }
 }
 
+/// Describes a cycle between queries: computing the value of the
+/// first participant (transitively) required the value of that same
+/// query. Returned by [the `try_get` method].
+///
+/// [the `try_get` method]: struct.QueryTable.html#method.try_get
+pub struct Cycle<DB: Database> {
+    participants: Vec<DB::DatabaseKey>,
+}
+
+impl<DB: Database> Cycle<DB> {
+    pub(crate) fn new(participants: Vec<DB::DatabaseKey>) -> Self {
+        Cycle { participants }
+    }
+
+    /// The database-keys of the queries that take part in the cycle,
+    /// starting with the query that was re-entered. Each entry
+    /// implements `Debug`.
+    pub fn participants(&self) -> &[DB::DatabaseKey] {
+        &self.participants
+    }
+}
+
+impl<DB: Database> Clone for Cycle<DB> {
+    fn clone(&self) -> Self {
+        Cycle {
+            participants: self.participants.clone(),
+        }
+    }
+}
+
+impl<DB: Database> fmt::Debug for Cycle<DB> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Cycle")
+            .field("participants", &self.participants)
+            .finish()
+    }
+}
+
+impl<DB: Database> fmt::Display for Cycle<DB> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "cycle detected:")?;
+        for database_key in &self.participants {
+            write!(fmt, "\n- {:?}", database_key)?;
+        }
+        Ok(())
+    }
+}
+
+impl<DB: Database> std::error::Error for Cycle<DB> {}
+
+/// Indicates that a query was canceled, because an input is about to
+/// be changed: its result would be of no use. Queries that are
+/// canceled unwind with `Canceled` as the panic payload (see
+/// `Runtime::unwind_if_canceled`); use `Snapshot::catch_canceled` to
+/// turn that into a `Result`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Canceled;
+
+impl Canceled {
+    /// Unwinds with `Canceled` as the panic payload (without invoking
+    /// the panic hook).
+    pub fn throw() -> ! {
+        std::panic::resume_unwind(Box::new(Canceled))
+    }
+}
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "canceled")
+    }
+}
+
+impl std::error::Error for Canceled {}
+
+/// Returned by [the `try_set` method] (and similar methods) if the
+/// inputs could not be changed in time, because snapshots of the
+/// database were still alive (see `ParallelDatabase::snapshot`).
+///
+/// [the `try_set` method]: struct.QueryTableMut.html#method.try_set
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotsAlive {
+    snapshots: Vec<RuntimeId>,
+}
+
+impl SnapshotsAlive {
+    /// The ids of the runtimes of the snapshots that were alive.
+    pub fn snapshots(&self) -> &[RuntimeId] {
+        &self.snapshots
+    }
+}
+
+impl fmt::Display for SnapshotsAlive {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "timed out waiting for snapshots {:?}", self.snapshots)
+    }
+}
+
+impl std::error::Error for SnapshotsAlive {}
+
 /// Trait implements by all of the "special types" associated with
 /// each of your queries.
 pub trait Query<DB: Database>: Debug + Default + Sized + 'static {
@@ -426,6 +791,22 @@ pub trait Query<DB: Database>: Debug + Default + Sized + 'static {
 
     /// Create group key for this query.
     fn group_key(key: Self::Key) -> Self::GroupKey;
+
+    /// The number of bytes that `key` owns on the heap, for
+    /// `Database::memory_report` (see `#[salsa::heap_size]`). Zero by
+    /// default.
+    fn key_heap_size(key: &Self::Key) -> usize {
+        let _ = key;
+        0
+    }
+
+    /// The number of bytes that `value` owns on the heap, for
+    /// `Database::memory_report` (see `#[salsa::heap_size]`). Zero by
+    /// default.
+    fn value_heap_size(value: &Self::Value) -> usize {
+        let _ = value;
+        0
+    }
 }
 
 /// Return value from [the `query` method] on `Database`.
@@ -451,15 +832,66 @@ where
     /// invoke the trait method directly. Note that for variadic
     /// queries (those with no inputs, or those with more than one
     /// input) the key will be a tuple.
+    ///
+    /// # Panics
+    ///
+    /// Panics if computing the value for `key` (transitively) requires
+    /// the value for `key` itself and the query cannot recover from
+    /// the cycle. Use `try_get` to handle cycles gracefully.
     pub fn get(&self, key: Q::Key) -> Q::Value {
+        self.try_get(key)
+            .unwrap_or_else(|cycle| panic!("{}", cycle))
+    }
+
+    /// Like `get`, but returns a description of the cycle instead of
+    /// panicking if computing the value for `key` (transitively)
+    /// requires the value for `key` itself.
+    pub fn try_get(&self, key: Q::Key) -> Result<Q::Value, Cycle<DB>> {
         let database_key = self.database_key(&key);
-        self.storage
-            .try_fetch(self.db, &key, &database_key)
-            .unwrap_or_else(|CycleDetected| {
-                self.db
-                    .salsa_runtime()
-                    .report_unexpected_cycle(database_key)
-            })
+        self.storage.try_fetch(self.db, &key, &database_key)
+    }
+
+    /// Like `get`, but returns a future, for use with an async
+    /// executor: if another thread is currently computing the value
+    /// for `key`, polling the future yields rather than blocking the
+    /// current thread until it is done. Queries themselves are still
+    /// executed synchronously, while polling.
+    ///
+    /// Only waiting for the value for `key` itself yields. If computing
+    /// it (or checking that its memoized value is still valid) requires
+    /// a value that another thread is computing, polling blocks the
+    /// current thread until that value is available, as `get` does.
+    ///
+    /// The future holds a snapshot of the database (see
+    /// `ParallelDatabase::snapshot`), and so can be sent to another
+    /// thread. Like any snapshot, it prevents inputs from being set
+    /// until it is dropped: to cancel the query, drop the future.
+    ///
+    /// # Panics
+    ///
+    /// Panics if invoked during a query computation, or if computing
+    /// the value for `key` (transitively) requires the value for `key`
+    /// itself and the query cannot recover from the cycle.
+    pub fn get_async(&self, key: Q::Key) -> QueryFuture<DB, Q>
+    where
+        DB: ParallelDatabase,
+    {
+        QueryFuture {
+            db: self.db.snapshot(),
+            key,
+        }
+    }
+
+    /// Like `get`, but for input queries: returns `None` instead of
+    /// panicking if no value was set for `key` (or if it was removed).
+    /// Either way, the read is tracked like any other: a query that
+    /// observed `key` as absent is re-executed once it is set.
+    pub fn get_if_set(&self, key: Q::Key) -> Option<Q::Value>
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        let database_key = self.database_key(&key);
+        self.storage.try_fetch_if_set(self.db, &key, &database_key)
     }
 
     /// Remove all values for this query that have not been used in
@@ -471,11 +903,57 @@ where
         self.storage.sweep(self.db, strategy);
     }
 
+    /// Sets the maximum number of memoized values to retain for this
+    /// query; the values of the least recently used keys beyond that
+    /// are discarded (their dependencies are kept, so discarded
+    /// values are simply recomputed when next needed). A capacity of
+    /// zero means "unbounded", which is the default unless the query
+    /// has a `#[salsa::lru]` attribute. Uses are only tracked while
+    /// the capacity is non-zero, so values computed while the query
+    /// was unbounded are not evicted.
+    pub fn set_lru_capacity(&self, capacity: usize)
+    where
+        Q::Storage: plumbing::LruQueryStorageOps,
+    {
+        self.storage.set_lru_capacity(capacity);
+    }
+
     fn database_key(&self, key: &Q::Key) -> DB::DatabaseKey {
         <DB as plumbing::GetQueryTable<Q>>::database_key(&self.db, key.clone())
     }
 }
 
+/// Return value from [the `get_async` method] on `QueryTable`; resolves
+/// to the value of the query `Q` for a given key.
+///
+/// [the `get_async` method]: struct.QueryTable.html#method.get_async
+pub struct QueryFuture<DB, Q>
+where
+    DB: ParallelDatabase + plumbing::GetQueryTable<Q>,
+    Q: Query<DB>,
+{
+    db: Snapshot<DB>,
+    key: Q::Key,
+}
+
+impl<DB, Q> Future for QueryFuture<DB, Q>
+where
+    DB: ParallelDatabase + plumbing::GetQueryTable<Q>,
+    Q: Query<DB>,
+{
+    type Output = Q::Value;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Q::Value> {
+        let db = &*self.db;
+        let table = <DB as plumbing::GetQueryTable<Q>>::get_query_table(db);
+        let database_key = table.database_key(&self.key);
+        table
+            .storage
+            .poll_fetch(db, &self.key, &database_key, cx)
+            .map(|result| result.unwrap_or_else(|cycle| panic!("{}", cycle)))
+    }
+}
+
 /// Return value from [the `query_mut` method] on `Database`.
 /// Gives access to the `set` method, notably, that is used to
 /// set the value of an input query.
@@ -510,9 +988,88 @@ where
     pub fn set(&self, key: Q::Key, value: Q::Value)
     where
         Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        self.set_with_durability(key, value, Durability::Low);
+    }
+
+    /// Like `set`, but gives up if the value cannot be assigned within
+    /// `timeout`, because snapshots of the database are still alive,
+    /// rather than blocking until they are dropped. Must be used
+    /// outside of an active query computation.
+    ///
+    /// Like `set`, this signals cancellation while waiting; see
+    /// `Runtime::try_next_revision` for what that means if it times
+    /// out.
+    pub fn try_set(
+        &self,
+        key: Q::Key,
+        value: Q::Value,
+        timeout: Duration,
+    ) -> Result<(), SnapshotsAlive>
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        self.db
+            .salsa_runtime()
+            .try_transaction(timeout, || self.set(key, value))
+    }
+
+    /// Assign a value to an "input query", with the given
+    /// durability. Giving rarely changing inputs a high durability
+    /// lets salsa skip re-validating derived values that only depend
+    /// on such inputs when some less durable input changes. Must be
+    /// used outside of an active query computation.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    pub fn set_with_durability(&self, key: Q::Key, value: Q::Value, durability: Durability)
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        self.storage
+            .set(self.db, &key, &self.database_key(&key), value, durability);
+    }
+
+    /// Like `set`, but leaves the input untouched if it already has a
+    /// value equal to `value` (with `Durability::Low`): no new
+    /// revision is started, and so queries that read the input are
+    /// not invalidated. Must be used outside of an active query
+    /// computation.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    pub fn set_if_changed(&self, key: Q::Key, value: Q::Value)
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        Q::Value: Eq,
+    {
+        self.set_if_changed_with_durability(key, value, Durability::Low);
+    }
+
+    /// Like `set_with_durability`, but leaves the input untouched if
+    /// it already has a value equal to `value` with the given
+    /// durability; see `set_if_changed`. Must be used outside of an
+    /// active query computation.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    pub fn set_if_changed_with_durability(
+        &self,
+        key: Q::Key,
+        value: Q::Value,
+        durability: Durability,
+    ) where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        Q::Value: Eq,
     {
         self.storage
-            .set(self.db, &key, &self.database_key(&key), value);
+            .set_if_changed(self.db, &key, &self.database_key(&key), value, durability);
     }
 
     /// Assign a value to an "input query", with the additional
@@ -530,6 +1087,109 @@ where
         self.storage
             .set_constant(self.db, &key, &self.database_key(&key), value);
     }
+
+    /// Removes the value of an "input query", so that `get` panics
+    /// and `get_if_set` returns `None` for `key` until a new value is
+    /// set. Queries that read the old value are invalidated as if it
+    /// had changed. Does nothing if no value is set for `key`. Must be
+    /// used outside of an active query computation.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    pub fn remove(&self, key: Q::Key)
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        self.storage.remove(self.db, &key, &self.database_key(&key));
+    }
+}
+
+/// Given to the closure of [the `transaction` method] on `Database`,
+/// to change the values of input queries. All changes made through a
+/// `Transaction` are applied in the same revision.
+///
+/// [the `transaction` method]: trait.Database#method.transaction
+pub struct Transaction<'me, DB: Database> {
+    db: &'me DB,
+}
+
+impl<DB> Transaction<'_, DB>
+where
+    DB: Database,
+{
+    /// Assign a value to the input query `Q`; see `QueryTableMut::set`.
+    pub fn set<Q>(&self, key: Q::Key, value: Q::Value)
+    where
+        Q: Query<DB>,
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        DB: plumbing::GetQueryTable<Q>,
+    {
+        self.set_with_durability::<Q>(key, value, Durability::Low);
+    }
+
+    /// Assign a value to the input query `Q`, with the given
+    /// durability; see `QueryTableMut::set_with_durability`.
+    pub fn set_with_durability<Q>(&self, key: Q::Key, value: Q::Value, durability: Durability)
+    where
+        Q: Query<DB>,
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        DB: plumbing::GetQueryTable<Q>,
+    {
+        self.query_mut::<Q>()
+            .set_with_durability(key, value, durability);
+    }
+
+    /// Assign a value to the input query `Q`, unless it already has an
+    /// equal value; see `QueryTableMut::set_if_changed`.
+    pub fn set_if_changed<Q>(&self, key: Q::Key, value: Q::Value)
+    where
+        Q: Query<DB>,
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        Q::Value: Eq,
+        DB: plumbing::GetQueryTable<Q>,
+    {
+        self.set_if_changed_with_durability::<Q>(key, value, Durability::Low);
+    }
+
+    /// Assign a value to the input query `Q`, with the given
+    /// durability, unless it already has an equal value with that
+    /// durability; see `QueryTableMut::set_if_changed_with_durability`.
+    pub fn set_if_changed_with_durability<Q>(
+        &self,
+        key: Q::Key,
+        value: Q::Value,
+        durability: Durability,
+    ) where
+        Q: Query<DB>,
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        Q::Value: Eq,
+        DB: plumbing::GetQueryTable<Q>,
+    {
+        self.query_mut::<Q>()
+            .set_if_changed_with_durability(key, value, durability);
+    }
+
+    /// Removes the value of the input query `Q`; see
+    /// `QueryTableMut::remove`.
+    pub fn remove<Q>(&self, key: Q::Key)
+    where
+        Q: Query<DB>,
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        DB: plumbing::GetQueryTable<Q>,
+    {
+        self.query_mut::<Q>().remove(key);
+    }
+
+    fn query_mut<Q>(&self) -> QueryTableMut<'_, DB, Q>
+    where
+        Q: Query<DB>,
+        DB: plumbing::GetQueryTable<Q>,
+    {
+        let table = <DB as plumbing::GetQueryTable<Q>>::get_query_table(self.db);
+        QueryTableMut::new(table.db, table.storage)
+    }
 }
 
 // Re-export the procedural macros.
@@ -0,0 +1,89 @@
+use parking_lot::Mutex;
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tracks which keys of a query were used most recently, so that the
+/// values of the others can be discarded once there are more than
+/// `capacity` of them. A capacity of zero means "unbounded", in which
+/// case no bookkeeping is done at all.
+pub(crate) struct Lru<K> {
+    capacity: AtomicUsize,
+    state: Mutex<LruState<K>>,
+}
+
+struct LruState<K> {
+    /// Incremented on every use; the key with the smallest tick is
+    /// the least recently used one.
+    next_tick: u64,
+
+    /// The tick at which each key was last used.
+    ticks: FxHashMap<K, u64>,
+
+    /// The inverse of `ticks`, ordered from least to most recently
+    /// used.
+    order: BTreeMap<u64, K>,
+}
+
+impl<K> Lru<K>
+where
+    K: Clone + Eq + Hash,
+{
+    pub(crate) fn new(capacity: usize) -> Self {
+        Lru {
+            capacity: AtomicUsize::new(capacity),
+            state: Mutex::new(LruState {
+                next_tick: 0,
+                ticks: FxHashMap::default(),
+                order: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Changes the capacity, returning the keys that no longer fit.
+    pub(crate) fn set_capacity(&self, capacity: usize) -> Vec<K> {
+        let mut state = self.state.lock();
+        self.capacity.store(capacity, Ordering::SeqCst);
+        if capacity == 0 {
+            state.ticks.clear();
+            state.order.clear();
+            return vec![];
+        }
+        state.evict(capacity)
+    }
+
+    /// Marks `key` as the most recently used key, returning the keys
+    /// that have to be evicted to make room for it (if any).
+    pub(crate) fn record_use(&self, key: &K) -> Vec<K> {
+        let capacity = self.capacity.load(Ordering::SeqCst);
+        if capacity == 0 {
+            return vec![];
+        }
+
+        let mut state = self.state.lock();
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        if let Some(old_tick) = state.ticks.insert(key.clone(), tick) {
+            state.order.remove(&old_tick);
+        }
+        state.order.insert(tick, key.clone());
+        state.evict(capacity)
+    }
+}
+
+impl<K> LruState<K>
+where
+    K: Clone + Eq + Hash,
+{
+    fn evict(&mut self, capacity: usize) -> Vec<K> {
+        let mut evicted = vec![];
+        while self.order.len() > capacity {
+            let tick = *self.order.keys().next().unwrap();
+            let key = self.order.remove(&tick).unwrap();
+            self.ticks.remove(&key);
+            evicted.push(key);
+        }
+        evicted
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::mem::size_of;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The memory used by the query `query`; see `Database::memory_report`.
+///
+/// The sizes are approximate: they do not include the overhead of the
+/// hash tables that store the entries, and heap memory is only
+/// included for queries with `#[salsa::heap_size]`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryMemoryUsage {
+    /// The name of the query type (e.g., `MyQuery`).
+    pub query: String,
+
+    /// The number of keys stored by the query.
+    pub entries: usize,
+
+    /// The bytes used by the keys.
+    pub key_bytes: usize,
+
+    /// The bytes used by the stored values.
+    pub value_bytes: usize,
+
+    /// The bytes used by the sets of queries that each derived value
+    /// depends on. Always zero for inputs and interned queries.
+    ///
+    /// This is an estimate, based on the capacity of each set and the
+    /// layout of its entries. A set that is shared by several values
+    /// is counted once for each of them.
+    pub inputs_bytes: usize,
+}
+
+/// The number of bytes a value owns on the heap, not counting the
+/// `size_of` the value itself. Implement it for the keys and values
+/// of queries with `#[salsa::heap_size]`, so that
+/// `Database::memory_report` includes their heap memory.
+pub trait HeapSize {
+    /// Returns the number of bytes that `self` owns on the heap.
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! impl_heap_size_zero {
+    ($($t:ty),*) => {
+        $(
+            impl HeapSize for $t {
+                fn heap_size(&self) -> usize {
+                    0
+                }
+            }
+        )*
+    };
+}
+
+impl_heap_size_zero!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    &'static str,
+    crate::InternId
+);
+
+macro_rules! impl_heap_size_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: HeapSize),*> HeapSize for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn heap_size(&self) -> usize {
+                let ($($name,)*) = self;
+                0 $(+ $name.heap_size())*
+            }
+        }
+    };
+}
+
+impl_heap_size_tuple!(A);
+impl_heap_size_tuple!(A, B);
+impl_heap_size_tuple!(A, B, C);
+impl_heap_size_tuple!(A, B, C, D);
+impl_heap_size_tuple!(A, B, C, D, E);
+impl_heap_size_tuple!(A, B, C, D, E, F);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        size_of::<T>() + (**self).heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+// Shared values are counted in full by each of their owners.
+impl<T: HeapSize + ?Sized> HeapSize for Arc<T> {
+    fn heap_size(&self) -> usize {
+        std::mem::size_of_val(&**self) + (**self).heap_size()
+    }
+}
+
+impl<T: HeapSize + ?Sized> HeapSize for Rc<T> {
+    fn heap_size(&self) -> usize {
+        std::mem::size_of_val(&**self) + (**self).heap_size()
+    }
+}
+
+impl HeapSize for str {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: HeapSize> HeapSize for [T] {
+    fn heap_size(&self) -> usize {
+        self.iter().map(HeapSize::heap_size).sum()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize, S: BuildHasher> HeapSize for HashMap<K, V, S> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<(K, V)>()
+            + self
+                .iter()
+                .map(|(key, value)| key.heap_size() + value.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl<T: HeapSize, S: BuildHasher> HeapSize for HashSet<T, S> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for BTreeMap<K, V> {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<(K, V)>()
+            + self
+                .iter()
+                .map(|(key, value)| key.heap_size() + value.heap_size())
+                .sum::<usize>()
+    }
+}
@@ -0,0 +1,179 @@
+//! Saving the query tables of a database and restoring them into a
+//! fresh database, so that the restored memos can be revalidated
+//! instead of re-executed.
+//!
+//! Each query storage encodes its entries into a separate buffer; the
+//! saved database is the current revision plus the list of those
+//! buffers, in the order in which the queries were declared.
+//! Dependency edges refer to other queries through their database-key,
+//! which is encoded as the index of its query group, the index of the
+//! query within the group, and the key.
+
+use crate::runtime::Revision;
+use crate::Database;
+use crate::Durability;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io;
+
+/// Implemented by storage of queries whose keys and values can be
+/// persisted.
+pub trait PersistentQueryStorageOps<DB: Database> {
+    /// Encodes the entries of this storage.
+    fn save(&self, db: &DB) -> io::Result<Vec<u8>>;
+
+    /// Inserts the entries encoded by `save` into this storage.
+    fn load(&self, db: &DB, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// Implemented (by the `query_group` macro) for the storage of query
+/// groups whose queries can all be persisted.
+pub trait PersistentQueryGroup<DB: Database> {
+    /// Invokes `op` with the name and storage of each query in the
+    /// group, in declaration order.
+    fn for_each_persistent_query(
+        &self,
+        op: &mut dyn FnMut(&'static str, &dyn PersistentQueryStorageOps<DB>),
+    );
+}
+
+/// Implemented (by the `database` macro) for the storage of databases
+/// whose query groups can all be persisted.
+pub trait PersistentDatabaseOps<DB: Database> {
+    /// Invokes `op` with the name and storage of each query in the
+    /// database, in declaration order.
+    fn for_each_persistent_query(
+        db: &DB,
+        op: &mut dyn FnMut(&'static str, &dyn PersistentQueryStorageOps<DB>),
+    );
+}
+
+/// Implemented (by the macros) for group keys and database-keys whose
+/// query keys can all be persisted.
+///
+/// Like the other traits in this module, this trait is generic over the
+/// database type: the generated impls are generic too, so that their
+/// bounds are only checked for databases that are actually saved.
+pub trait PersistentDatabaseKey<DB: Database>: Sized {
+    /// Encodes the query and key identified by `self`.
+    fn encode(&self) -> io::Result<Vec<u8>>;
+
+    /// Decodes a key encoded by `encode`.
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedDatabase {
+    revision: Revision,
+    last_changed_revisions: Vec<Revision>,
+    queries: Vec<SavedQuery>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedQuery {
+    name: String,
+    bytes: Vec<u8>,
+}
+
+pub(crate) fn save_to<DB>(db: &DB, writer: impl io::Write) -> io::Result<()>
+where
+    DB: Database,
+    DB::DatabaseStorage: PersistentDatabaseOps<DB>,
+{
+    let runtime = db.salsa_runtime();
+    let mut queries = vec![];
+    let mut result = Ok(());
+    DB::DatabaseStorage::for_each_persistent_query(db, &mut |name, storage| {
+        if result.is_ok() {
+            result = storage.save(db).map(|bytes| {
+                queries.push(SavedQuery {
+                    name: name.to_string(),
+                    bytes,
+                })
+            });
+        }
+    });
+    result?;
+
+    let saved = SavedDatabase {
+        revision: runtime.current_revision(),
+        last_changed_revisions: [Durability::Low, Durability::Medium, Durability::High]
+            .iter()
+            .map(|&durability| runtime.last_changed_revision(durability))
+            .collect(),
+        queries,
+    };
+    bincode::serialize_into(writer, &saved).map_err(invalid_data)
+}
+
+pub(crate) fn load_from<DB>(db: &mut DB, reader: impl io::Read) -> io::Result<()>
+where
+    DB: Database,
+    DB::DatabaseStorage: PersistentDatabaseOps<DB>,
+{
+    let saved: SavedDatabase = bincode::deserialize_from(reader).map_err(invalid_data)?;
+    if saved.last_changed_revisions.len() != Durability::LEN {
+        return Err(invalid_data("unexpected number of durabilities"));
+    }
+
+    let db = &*db;
+    let mut queries = saved.queries.iter();
+    let mut result = Ok(());
+    db.salsa_runtime().with_restored_revisions(
+        saved.revision,
+        &saved.last_changed_revisions,
+        || {
+            DB::DatabaseStorage::for_each_persistent_query(db, &mut |name, storage| {
+                if result.is_err() {
+                    return;
+                }
+                result = match queries.next() {
+                    Some(query) if query.name == name => storage.load(db, &query.bytes),
+                    _ => Err(invalid_data(format!(
+                        "saved queries do not match the database at `{}`",
+                        name
+                    ))),
+                };
+            });
+        },
+    )?;
+    result?;
+
+    if queries.next().is_some() {
+        return Err(invalid_data("saved queries do not match the database"));
+    }
+    Ok(())
+}
+
+/// Encodes a key or value of a query.
+pub fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    bincode::serialize(value).map_err(invalid_data)
+}
+
+/// Decodes a key or value encoded by `encode`.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+    bincode::deserialize(bytes).map_err(invalid_data)
+}
+
+/// Prefixes `bytes` with `tag`, which identifies the variant of a
+/// group key or database-key.
+pub fn encode_tagged(tag: u32, bytes: io::Result<Vec<u8>>) -> io::Result<Vec<u8>> {
+    let mut tagged = tag.to_le_bytes().to_vec();
+    tagged.extend(bytes?);
+    Ok(tagged)
+}
+
+/// Splits the tag written by `encode_tagged` from the rest of `bytes`.
+pub fn decode_tag(bytes: &[u8]) -> io::Result<(u32, &[u8])> {
+    if bytes.len() < 4 {
+        return Err(invalid_data("truncated database key"));
+    }
+    let (tag, rest) = bytes.split_at(4);
+    Ok((u32::from_le_bytes(tag.try_into().unwrap()), rest))
+}
+
+/// The error reported when saved data cannot be loaded.
+pub fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -1,21 +1,64 @@
 #![allow(missing_docs)]
 
+use crate::debug::DependencyGraph;
 use crate::debug::TableEntry;
+use crate::Cycle;
 use crate::Database;
+use crate::Durability;
 use crate::Query;
+use crate::QueryMemoryUsage;
 use crate::QueryTable;
 use crate::QueryTableMut;
 use crate::SweepStrategy;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::task::{Context, Poll};
 
+pub use crate::derived::CustomEqStorage;
 pub use crate::derived::DependencyStorage;
+pub use crate::derived::HashedStorage;
 pub use crate::derived::MemoizedStorage;
 pub use crate::derived::VolatileStorage;
 pub use crate::input::InputStorage;
+pub use crate::interned::InternedStorage;
+pub use crate::interned::LookupInternedStorage;
+#[cfg(feature = "serde")]
+pub use crate::persist::{
+    decode, decode_tag, encode, encode_tagged, invalid_data, PersistentDatabaseKey,
+    PersistentDatabaseOps, PersistentQueryGroup, PersistentQueryStorageOps,
+};
 pub use crate::runtime::Revision;
+use crate::runtime::RuntimeId;
+#[cfg(feature = "serde")]
+pub use serde;
 
-pub struct CycleDetected;
+/// Expands to the given items if salsa was built with the `serde`
+/// feature, and to nothing otherwise. The procedural macros wrap the
+/// persistence impls in it, since they cannot see salsa's features.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __salsa_if_serde {
+    ($($items:tt)*) => { $($items)* };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __salsa_if_serde {
+    ($($items:tt)*) => {};
+}
+
+/// Internal marker indicating that a query was found to depend on
+/// itself; see `Cycle` for the user-facing description of a cycle.
+pub struct CycleDetected {
+    /// The runtime that detected the cycle.
+    pub(crate) from: RuntimeId,
+
+    /// The runtime computing the query that `from` attempted to
+    /// fetch; equal to `from` if the cycle is local to one runtime.
+    pub(crate) to: RuntimeId,
+}
 
 /// Defines various associated types. An impl of this
 /// should be generated for your query-context type automatically by
@@ -39,6 +82,12 @@ pub trait DatabaseStorageTypes: Sized {
 pub trait DatabaseOps: Sized {
     /// Executes the callback for each kind of query.
     fn for_each_query(&self, op: impl FnMut(&dyn QueryStorageMassOps<Self>));
+
+    /// Adds the entries of each query to `graph` (see
+    /// `debug::dependency_graph`).
+    fn fill_dependency_graph(&self, graph: &mut DependencyGraph<Self>)
+    where
+        Self: Database;
 }
 
 /// Internal operations performed on the query storage as a whole
@@ -47,6 +96,11 @@ pub trait DatabaseOps: Sized {
 pub trait QueryStorageMassOps<DB: Database> {
     /// Discards memoized values that are not up to date with the current revision.
     fn sweep(&self, db: &DB, strategy: SweepStrategy);
+
+    /// Returns the memory used by this storage (see
+    /// `Database::memory_report`), or `None` if it stores no entries
+    /// of its own.
+    fn memory_usage(&self, db: &DB) -> Option<QueryMemoryUsage>;
 }
 
 pub trait DatabaseKey<DB>: Clone + Debug + Eq + Hash + Send + Sync {
@@ -56,7 +110,41 @@ pub trait DatabaseKey<DB>: Clone + Debug + Eq + Hash + Send + Sync {
 }
 
 pub trait QueryFunction<DB: Database>: Query<DB> {
+    /// The initial number of memoized values to retain; zero means
+    /// "unbounded" (see `#[salsa::lru]`).
+    const LRU_CAPACITY: usize = 0;
+
+    /// Whether `recover` returns a value, i.e. whether this query can
+    /// recover from cycles (see `#[salsa::cycle]`).
+    const CYCLE_RECOVERY: bool = false;
+
     fn execute(db: &DB, key: Self::Key) -> Self::Value;
+
+    /// Invoked when computing the value for `key` was found to be
+    /// part of `cycle`. Returns the value to use in its place, or
+    /// `None` if this query cannot recover from cycles (see
+    /// `#[salsa::cycle]`).
+    fn recover(db: &DB, cycle: &Cycle<DB>, key: &Self::Key) -> Option<Self::Value> {
+        let _ = (db, cycle, key);
+        None
+    }
+
+    /// Whether to memoize the value of a memoized query for `key` (see
+    /// `#[salsa::memoize_if]`). If not, only its dependencies are
+    /// tracked, as with `#[salsa::dependencies]`.
+    fn memoize_value(key: &Self::Key) -> bool {
+        let _ = key;
+        true
+    }
+
+    /// Invoked when the value of a query with a custom equality (see
+    /// `#[salsa::eq_with]`) is recomputed, to tell whether it changed.
+    /// The default, used with `#[salsa::no_eq]`, is to consider
+    /// values to always have changed.
+    fn value_eq(old_value: &Self::Value, new_value: &Self::Value) -> bool {
+        let _ = (old_value, new_value);
+        false
+    }
 }
 
 /// The `GetQueryTable` trait makes the connection the *database type*
@@ -146,7 +234,23 @@ where
         db: &DB,
         key: &Q::Key,
         descriptor: &DB::DatabaseKey,
-    ) -> Result<Q::Value, CycleDetected>;
+    ) -> Result<Q::Value, Cycle<DB>>;
+
+    /// Like `try_fetch`, but if another runtime is currently
+    /// computing the value for `key`, returns `Poll::Pending` and
+    /// arranges for the waker of `cx` to be woken once it is done,
+    /// rather than blocking the current thread. Only used outside of
+    /// an active query computation. By default, never returns
+    /// `Poll::Pending`.
+    fn poll_fetch(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        descriptor: &DB::DatabaseKey,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Q::Value, Cycle<DB>>> {
+        Poll::Ready(self.try_fetch(db, key, descriptor))
+    }
 
     /// True if the query **may** have changed since the given
     /// revision. The query will answer this question with as much
@@ -181,6 +285,12 @@ where
     fn entries<C>(&self, db: &DB) -> C
     where
         C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>;
+
+    /// Adds the entries of this storage to `graph` (see
+    /// `debug::dependency_graph`).
+    fn dependency_graph(&self, db: &DB, graph: &mut DependencyGraph<DB>)
+    where
+        DB: HasQueryGroup<Q::Group>;
 }
 
 /// An optional trait that is implemented for "user mutable" storage:
@@ -191,7 +301,14 @@ where
     DB: Database,
     Q: Query<DB>,
 {
-    fn set(&self, db: &DB, key: &Q::Key, descriptor: &DB::DatabaseKey, new_value: Q::Value);
+    fn set(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        descriptor: &DB::DatabaseKey,
+        new_value: Q::Value,
+        durability: Durability,
+    );
 
     fn set_constant(
         &self,
@@ -200,4 +317,35 @@ where
         descriptor: &DB::DatabaseKey,
         new_value: Q::Value,
     );
+
+    /// Like `set`, but does nothing (and, in particular, does not
+    /// start a new revision) if `key` already has a value equal to
+    /// `new_value`, with the same durability.
+    fn set_if_changed(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        descriptor: &DB::DatabaseKey,
+        new_value: Q::Value,
+        durability: Durability,
+    ) where
+        Q::Value: Eq;
+
+    fn remove(&self, db: &DB, key: &Q::Key, descriptor: &DB::DatabaseKey);
+
+    /// Returns the value of `key`, or `None` if it was never set or
+    /// has been removed; in either case, the read is recorded as a
+    /// dependency of the active query.
+    fn try_fetch_if_set(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        descriptor: &DB::DatabaseKey,
+    ) -> Option<Q::Value>;
+}
+
+/// An optional trait that is implemented for storage which can
+/// bound the number of memoized values it retains.
+pub trait LruQueryStorageOps: Default {
+    fn set_lru_capacity(&self, new_capacity: usize);
 }
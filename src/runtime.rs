@@ -1,13 +1,19 @@
-use crate::{Database, Event, EventKind, SweepStrategy};
+use crate::plumbing::CycleDetected;
+use crate::stats::QueryStatsCollector;
+use crate::{
+    Canceled, Cycle, Database, Durability, Event, EventKind, ExecuteReason, SnapshotsAlive,
+    SweepStrategy,
+};
 use lock_api::{RawRwLock, RawRwLockRecursive};
 use log::debug;
 use parking_lot::{Mutex, RwLock};
-use rustc_hash::{FxHashMap, FxHasher};
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
 use smallvec::SmallVec;
-use std::fmt::Write;
+use std::backtrace::Backtrace;
 use std::hash::BuildHasherDefault;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 pub(crate) type FxIndexSet<K> = indexmap::IndexSet<K, BuildHasherDefault<FxHasher>>;
 
@@ -30,6 +36,11 @@ pub struct Runtime<DB: Database> {
     /// lock.
     revision_guard: Option<RevisionGuard<DB>>,
 
+    /// If this runtime was created by `ParallelDatabase::fork`, then
+    /// `fork` records the reads of its queries on behalf of the query
+    /// that forked it.
+    fork: Option<Fork<DB>>,
+
     /// Local state that is specific to this runtime (thread).
     local_state: LocalState<DB>,
 
@@ -45,6 +56,7 @@ where
         Runtime {
             id: RuntimeId { counter: 0 },
             revision_guard: None,
+            fork: None,
             shared_state: Default::default(),
             local_state: Default::default(),
         }
@@ -94,24 +106,59 @@ where
             "invoked `snapshot` with a non-matching database"
         );
 
-        if self.local_state.query_in_progress() {
+        if self.local_state.query_in_progress() && !self.local_state.is_forking() {
             panic!("it is not legal to `snapshot` during a query (see salsa-rs/salsa#80)");
         }
 
-        let revision_guard = RevisionGuard::new(&self.shared_state);
-
         let id = RuntimeId {
             counter: self.shared_state.next_id.fetch_add(1, Ordering::SeqCst),
         };
 
+        let revision_guard = RevisionGuard::new(&self.shared_state, id, self.priority(self.id));
+
         Runtime {
             id,
             revision_guard: Some(revision_guard),
+            fork: self.new_fork(id),
             shared_state: self.shared_state.clone(),
             local_state: Default::default(),
         }
     }
 
+    /// Invoked by `snapshot`: when forking, returns the `Fork` of the
+    /// new runtime `id`. Forks of a fork that are created outside of a
+    /// query record their reads on behalf of the same query.
+    fn new_fork(&self, id: RuntimeId) -> Option<Fork<DB>> {
+        if !self.local_state.is_forking() {
+            return None;
+        }
+
+        let reads = if self.local_state.query_in_progress() {
+            self.shared_state
+                .dependency_graph
+                .lock()
+                .forks
+                .insert(id, (self.id, self.local_state.query_stack_keys()));
+            self.local_state.add_fork()
+        } else {
+            self.fork.as_ref()?.reads.clone()
+        };
+
+        let mut ancestors = match &self.fork {
+            Some(fork) => fork.ancestors.clone(),
+            None => Vec::new(),
+        };
+        ancestors.push(self.id);
+
+        Some(Fork { reads, ancestors })
+    }
+
+    /// Invokes `op`, during which `snapshot` creates forks; see
+    /// `ParallelDatabase::fork`.
+    pub(crate) fn forking<T>(&self, op: impl FnOnce() -> T) -> T {
+        self.local_state.with_forking(op)
+    }
+
     /// Indicates that some input to the system has changed and hence
     /// that memoized values **may** be invalidated. This cannot be
     /// invoked while query computation is in progress.
@@ -122,8 +169,26 @@ where
     /// "volatile" input that you must poll from time to time; in that
     /// case, you can wrap the input with a "no-storage" query and
     /// invoke this method from time to time.
+    ///
+    /// The new revision is treated as a change to a `Durability::Low`
+    /// input.
     pub fn next_revision(&self) {
-        self.with_incremented_revision(|_| ());
+        self.with_incremented_revision(|_| Durability::Low);
+    }
+
+    /// Like `next_revision`, but gives up if the new revision cannot
+    /// be started within `timeout`, because snapshots are still alive
+    /// (and hence hold the global query lock); the error identifies
+    /// them.
+    ///
+    /// While waiting, the current revision is marked as canceled (see
+    /// `is_current_revision_canceled`), to bring the snapshots to a
+    /// close; if we time out, it is no longer canceled. Queries that
+    /// unwound in the meantime (see `unwind_if_canceled`) left no
+    /// trace, but those that returned a special value instead are
+    /// memoized with it for the current revision.
+    pub fn try_next_revision(&self, timeout: Duration) -> Result<(), SnapshotsAlive> {
+        self.try_with_incremented_revision(timeout, |_| Durability::Low)
     }
 
     /// Default implementation for `Database::sweep_all`.
@@ -149,6 +214,146 @@ where
         self.local_state.active_query()
     }
 
+    /// Starts (or stops) collecting statistics about the executions
+    /// of derived queries, which are reported by
+    /// `Database::query_stats`. The collection is disabled by
+    /// default; it is shared by all snapshots of the database.
+    pub fn set_query_stats_enabled(&self, enabled: bool) {
+        self.shared_state.query_stats.set_enabled(enabled);
+    }
+
+    /// Discards the statistics collected so far.
+    pub fn reset_query_stats(&self) {
+        self.shared_state.query_stats.reset();
+    }
+
+    pub(crate) fn query_stats(&self) -> &QueryStatsCollector {
+        &self.shared_state.query_stats
+    }
+
+    /// Enables (or disables) automatic cancellation: when enabled,
+    /// each derived query checks whether the current revision is
+    /// canceled before it is validated or executed, and unwinds with
+    /// `Canceled` if so (see `unwind_if_canceled`). This way, long
+    /// chains of queries stop promptly when an input is set, without
+    /// checks in the query bodies. Disabled by default; the setting is
+    /// shared by all snapshots of the database.
+    pub fn set_automatic_cancellation(&self, enabled: bool) {
+        self.shared_state
+            .automatic_cancellation
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    /// Returns the snapshots of the database that are alive, ordered
+    /// by id. Each of them holds a read-lock on the global query lock
+    /// until it is dropped, which blocks attempts to set inputs (see
+    /// `Database::query_mut`).
+    pub fn live_snapshots(&self) -> Vec<SnapshotInfo> {
+        let mut snapshots: Vec<SnapshotInfo> = self
+            .shared_state
+            .snapshots
+            .lock()
+            .values()
+            .cloned()
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.id);
+        snapshots
+    }
+
+    /// Labels this runtime, which must be that of a snapshot, in the
+    /// result of `live_snapshots`; for example, with the name of the
+    /// task that is using the snapshot.
+    pub fn set_snapshot_label(&self, label: impl Into<String>) {
+        match self.shared_state.snapshots.lock().get_mut(&self.id) {
+            Some(snapshot) => snapshot.label = Some(label.into()),
+            None => panic!("`set_snapshot_label` invoked on a runtime that is not a snapshot"),
+        }
+    }
+
+    /// Sets the priority of this runtime, which must be that of a
+    /// snapshot. When a runtime blocks on a query that a runtime of
+    /// lower priority is computing, it asks that runtime to unwind
+    /// with `Canceled` (the next time it invokes `unwind_if_canceled`,
+    /// or checks for cancellation automatically), and then computes
+    /// the query itself. The database itself, and snapshots unless
+    /// told otherwise, have `Priority::Interactive`; snapshots taken
+    /// from a snapshot inherit its priority.
+    pub fn set_snapshot_priority(&self, priority: Priority) {
+        match self.shared_state.snapshots.lock().get_mut(&self.id) {
+            Some(snapshot) => snapshot.priority = priority,
+            None => panic!("`set_snapshot_priority` invoked on a runtime that is not a snapshot"),
+        }
+    }
+
+    /// The priority of the runtime `id`; see `set_snapshot_priority`.
+    fn priority(&self, id: RuntimeId) -> Priority {
+        self.shared_state
+            .snapshots
+            .lock()
+            .get(&id)
+            .map_or(Priority::Interactive, |snapshot| snapshot.priority)
+    }
+
+    /// Invoked when this runtime is about to block on a query that
+    /// `other_id` is computing: if `other_id` has a lower priority,
+    /// asks it to unwind, so that we can compute the query ourselves.
+    pub(crate) fn preempt_if_lower_priority(&self, other_id: RuntimeId) {
+        if self.priority(other_id) < self.priority(self.id) {
+            debug!("{:?}: preempting {:?}", self.id, other_id);
+            self.shared_state.preempted.lock().insert(other_id);
+        }
+    }
+
+    /// If another runtime asked this one to unwind while it computes
+    /// a query (see `preempt_if_lower_priority`), unwinds with
+    /// `Canceled`; the queries that we leave unfinished are then
+    /// retried by the runtimes that are blocked on them, rather than
+    /// unwinding as well.
+    fn unwind_if_preempted(&self) {
+        if self.shared_state.preempted.lock().remove(&self.id)
+            && self.local_state.query_in_progress()
+        {
+            debug!("{:?}: preempted", self.id);
+            self.local_state.set_preempted();
+            Canceled::throw();
+        }
+    }
+
+    /// True if this runtime is unwinding because it was preempted
+    /// (see `unwind_if_preempted`). Invoked once the placeholder of
+    /// each query that we leave unfinished has been removed.
+    pub(crate) fn take_preempted(&self) -> bool {
+        self.local_state.take_preempted()
+    }
+
+    /// Starts (or stops) capturing a backtrace whenever a snapshot is
+    /// created, which is reported by `live_snapshots`. Disabled by
+    /// default, as capturing backtraces is slow; the setting is shared
+    /// by all snapshots of the database.
+    pub fn set_snapshot_backtraces(&self, enabled: bool) {
+        self.shared_state
+            .snapshot_backtraces
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    /// If automatic cancellation is enabled and the current revision
+    /// is canceled (or this runtime was preempted), unwinds with
+    /// `Canceled`. Unlike `is_current_revision_canceled`, this does not
+    /// record a read: either we unwind, or nothing was observed.
+    pub(crate) fn unwind_if_automatically_canceled(&self) {
+        if self
+            .shared_state
+            .automatic_cancellation
+            .load(Ordering::Relaxed)
+        {
+            if self.pending_revision() > self.current_revision() {
+                debug!("unwind_if_automatically_canceled: canceled");
+                Canceled::throw();
+            }
+            self.unwind_if_preempted();
+        }
+    }
+
     /// Read current value of the revision counter.
     #[inline]
     pub(crate) fn current_revision(&self) -> Revision {
@@ -157,6 +362,16 @@ where
         }
     }
 
+    /// The last revision in which an input with at least the given
+    /// durability changed.
+    #[inline]
+    pub(crate) fn last_changed_revision(&self, durability: Durability) -> Revision {
+        Revision {
+            generation: self.shared_state.last_changed_revisions[durability.index()]
+                .load(Ordering::SeqCst) as u64,
+        }
+    }
+
     /// Read current value of the revision counter.
     #[inline]
     fn pending_revision(&self) -> Revision {
@@ -186,22 +401,7 @@ where
     ///   * API around top-level queries checks if the result is `Ok` or
     ///     `Err(Canceled)`.
     ///
-    /// The `panic` approach works in a similar way:
-    ///
-    ///   * Some queries invoke `is_current_revision_canceled` and
-    ///     panic with a special value, like `Canceled`, if it returns
-    ///     true.
-    ///   * The implementation of `Database` trait overrides
-    ///     `on_propagated_panic` to throw this special value as well.
-    ///     This way, panic gets propagated naturally through dependant
-    ///     queries, even across the threads.
-    ///   * API around top-level queries converts a `panic` into `Result` by
-    ///     catching the panic (using either `std::panic::catch_unwind` or
-    ///     threads) and downcasting the payload to `Canceled` (re-raising
-    ///     panic if downcast fails).
-    ///
-    /// Note that salsa is explicitly designed to be panic-safe, so cancellation
-    /// via unwinding is 100% valid approach to cancellation.
+    /// The unwinding approach is built in: see `unwind_if_canceled`.
     #[inline]
     pub fn is_current_revision_canceled(&self) -> bool {
         let current_revision = self.current_revision();
@@ -250,10 +450,33 @@ where
         }
     }
 
+    /// Unwinds with `Canceled` as the panic payload if the current
+    /// revision is canceled (see `is_current_revision_canceled`).
+    ///
+    /// Unwinding propagates naturally through dependent queries,
+    /// discarding their results, even across threads: a thread that
+    /// was blocked on a canceled query unwinds with `Canceled`, too.
+    /// Use `Snapshot::catch_canceled` around top-level queries to
+    /// convert the cancellation into a `Result`. Note that salsa is
+    /// explicitly designed to be panic-safe, so cancellation via
+    /// unwinding is a 100% valid approach to cancellation.
+    ///
+    /// This also unwinds if a runtime of higher priority is blocked
+    /// on a query that this one is computing (see
+    /// `set_snapshot_priority`).
+    pub fn unwind_if_canceled(&self) {
+        if self.is_current_revision_canceled() {
+            Canceled::throw();
+        }
+        self.unwind_if_preempted();
+    }
+
     /// Acquires the **global query write lock** (ensuring that no
     /// queries are executing) and then increments the current
     /// revision counter; invokes `op` with the global query write
-    /// lock still held.
+    /// lock still held. `op` returns the durability of the inputs
+    /// that it changed, which is recorded as the last changed revision
+    /// for that durability (and all lower ones).
     ///
     /// While we wait to acquire the global query write lock, this
     /// method will also increment `pending_revision_increments`, thus
@@ -263,13 +486,50 @@ where
     /// Note that, given our writer model, we can assume that only one
     /// thread is attempting to increment the global revision at a
     /// time.
-    pub(crate) fn with_incremented_revision<R>(&self, op: impl FnOnce(Revision) -> R) -> R {
+    ///
+    /// Within a transaction, the lock is already held and the revision
+    /// already incremented: `op` is invoked with the revision of the
+    /// transaction, and the durability it returns is recorded when the
+    /// transaction ends.
+    pub(crate) fn with_incremented_revision(&self, op: impl FnOnce(Revision) -> Durability) {
+        self.increment_revision(None, |revision| Some(op(revision)))
+            .unwrap_or_else(|_| unreachable!("no timeout"))
+    }
+
+    /// Like `with_incremented_revision`, but gives up if the global
+    /// query write lock cannot be acquired within `timeout`, returning
+    /// the ids of the snapshots that are still alive. In that case,
+    /// `op` is not invoked, and the cancellation flag is cleared
+    /// again (see `try_next_revision`).
+    pub(crate) fn try_with_incremented_revision(
+        &self,
+        timeout: Duration,
+        op: impl FnOnce(Revision) -> Durability,
+    ) -> Result<(), SnapshotsAlive> {
+        self.increment_revision(Some(timeout), |revision| Some(op(revision)))
+    }
+
+    /// Implements `with_incremented_revision`, except that `op` may
+    /// also return `None` if it did not change any input after all; in
+    /// that case, the revision is not incremented.
+    fn increment_revision(
+        &self,
+        timeout: Option<Duration>,
+        op: impl FnOnce(Revision) -> Option<Durability>,
+    ) -> Result<(), SnapshotsAlive> {
         log::debug!("increment_revision()");
 
         if !self.permits_increment() {
             panic!("increment_revision invoked during a query computation");
         }
 
+        if let Some(revision) = self.local_state.transaction_revision() {
+            if let Some(durability) = op(revision) {
+                self.local_state.record_transaction_change(durability);
+            }
+            return Ok(());
+        }
+
         // Set the `pending_revision` field so that people
         // know current revision is canceled.
         let current_revision = self
@@ -279,18 +539,114 @@ where
         assert!(current_revision != usize::max_value(), "revision overflow");
 
         // To modify the revision, we need the lock.
-        let _lock = self.shared_state.query_lock.write();
+        let _lock = match timeout {
+            None => self.shared_state.query_lock.write(),
+            Some(timeout) => match self.shared_state.query_lock.try_write_for(timeout) {
+                Some(lock) => lock,
+                None => {
+                    self.shared_state
+                        .pending_revision
+                        .fetch_sub(1, Ordering::SeqCst);
+                    let mut snapshots: Vec<RuntimeId> =
+                        self.shared_state.snapshots.lock().keys().cloned().collect();
+                    snapshots.sort();
+                    debug!("increment_revision: timed out, snapshots {:?}", snapshots);
+                    return Err(SnapshotsAlive { snapshots });
+                }
+            },
+        };
 
-        let old_revision = self.shared_state.revision.fetch_add(1, Ordering::SeqCst);
-        assert_eq!(current_revision, old_revision);
+        assert_eq!(
+            current_revision,
+            self.shared_state.revision.load(Ordering::SeqCst)
+        );
 
         let new_revision = Revision {
             generation: (current_revision + 1) as u64,
         };
 
-        debug!("increment_revision: incremented to {:?}", new_revision);
+        debug!("increment_revision: incrementing to {:?}", new_revision);
+
+        let mut guard = LastChangedGuard {
+            shared_state: &self.shared_state,
+            revision: new_revision,
+            durability: Some(Durability::High),
+        };
+        guard.durability = op(new_revision);
+
+        Ok(())
+    }
+
+    /// Increments the revision once, then invokes `op`; all inputs
+    /// that `op` changes are changed in that same revision. If `op`
+    /// changes no input, the revision is not incremented after all.
+    /// Cannot be nested. See `Database::transaction`.
+    pub(crate) fn transaction(&self, op: impl FnOnce()) {
+        self.increment_revision(None, |revision| {
+            let guard = self.local_state.open_transaction(revision);
+            op();
+            guard.close()
+        })
+        .unwrap_or_else(|_| unreachable!("no timeout"))
+    }
 
-        op(new_revision)
+    /// Like `transaction`, but gives up if the global query write lock
+    /// cannot be acquired within `timeout`; see
+    /// `try_with_incremented_revision`.
+    pub(crate) fn try_transaction(
+        &self,
+        timeout: Duration,
+        op: impl FnOnce(),
+    ) -> Result<(), SnapshotsAlive> {
+        self.increment_revision(Some(timeout), |revision| {
+            let guard = self.local_state.open_transaction(revision);
+            op();
+            guard.close()
+        })
+    }
+
+    /// Restores the current revision (and the last revision in which
+    /// an input of each durability changed) of a saved database,
+    /// then invokes `op` to restore its values. Only permitted
+    /// before any input has been set.
+    #[cfg(feature = "serde")]
+    pub(crate) fn with_restored_revisions(
+        &self,
+        revision: Revision,
+        last_changed_revisions: &[Revision],
+        op: impl FnOnce(),
+    ) -> std::io::Result<()> {
+        if !self.permits_increment() {
+            panic!("load_from invoked during a query computation");
+        }
+
+        let _lock = self.shared_state.query_lock.write();
+
+        if self.current_revision() != Revision::ZERO {
+            return Err(crate::persist::invalid_data(
+                "can only load into a database whose inputs were never set",
+            ));
+        }
+
+        debug!("with_restored_revisions: restored {:?}", revision);
+
+        let shared_state = &self.shared_state;
+        shared_state
+            .pending_revision
+            .store(revision.as_usize(), Ordering::SeqCst);
+        shared_state
+            .revision
+            .store(revision.as_usize(), Ordering::SeqCst);
+        for (last_changed, restored) in shared_state
+            .last_changed_revisions
+            .iter()
+            .zip(last_changed_revisions)
+        {
+            last_changed.store(restored.as_usize(), Ordering::SeqCst);
+        }
+
+        op();
+        Ok(())
     }
 
     pub(crate) fn permits_increment(&self) -> bool {
@@ -301,34 +657,50 @@ where
         &self,
         db: &DB,
         database_key: &DB::DatabaseKey,
+        reason: ExecuteReason<DB>,
+        cycle_recovery: bool,
         execute: impl FnOnce() -> V,
     ) -> ComputedQueryResult<DB, V> {
-        debug!("{:?}: execute_query_implementation invoked", database_key);
+        debug!(
+            "{:?}: execute_query_implementation invoked ({:?})",
+            database_key, reason
+        );
 
         db.salsa_event(|| Event {
             runtime_id: db.salsa_runtime().id(),
             kind: EventKind::WillExecute {
                 database_key: database_key.clone(),
+                reason: reason.clone(),
             },
         });
 
         // Push the active query onto the stack.
-        let active_query = self.local_state.push_query(database_key);
+        let active_query = self.local_state.push_query(database_key, cycle_recovery);
 
         // Execute user's code, accumulating inputs etc.
         let value = execute();
 
-        // Extract accumulated inputs.
+        // Extract accumulated inputs, including those of the forks.
+        let mut active_query = active_query.complete();
+        active_query.merge_forks();
+
+        // A request to preempt us that arrives once we finished is
+        // stale; drop it before it cancels our next query.
+        if self.revision_guard.is_some() && !self.local_state.query_in_progress() {
+            self.shared_state.preempted.lock().remove(&self.id);
+        }
         let ActiveQuery {
             subqueries,
             changed_at,
+            cycle,
             ..
-        } = active_query.complete();
+        } = active_query;
 
         ComputedQueryResult {
             value,
             changed_at,
             subqueries,
+            cycle,
         }
     }
 
@@ -341,12 +713,16 @@ where
     /// - `changed_revision`: the last revision in which the result of that
     ///   query had changed
     pub(crate) fn report_query_read(&self, database_key: &DB::DatabaseKey, changed_at: ChangedAt) {
-        self.local_state.report_query_read(database_key, changed_at);
+        if !self.report_to_fork(|reads| reads.add_read(database_key, changed_at)) {
+            self.local_state.report_query_read(database_key, changed_at);
+        }
     }
 
     pub(crate) fn report_untracked_read(&self) {
-        self.local_state
-            .report_untracked_read(self.current_revision());
+        let revision = self.current_revision();
+        if !self.report_to_fork(|reads| reads.add_untracked_read(revision)) {
+            self.local_state.report_untracked_read(revision);
+        }
     }
 
     /// An "anonymous" read is a read that doesn't come from executing
@@ -358,34 +734,154 @@ where
     ///
     /// This is used when queries check if they have been canceled.
     fn report_anon_read(&self, revision: Revision) {
-        self.local_state.report_anon_read(revision)
+        if !self.report_to_fork(|reads| reads.add_anon_read(revision)) {
+            self.local_state.report_anon_read(revision)
+        }
+    }
+
+    /// If this runtime is a fork and no query is in progress, reports
+    /// a read (with `op`) on behalf of the query that forked it and
+    /// returns true.
+    fn report_to_fork(&self, op: impl FnOnce(&mut ActiveQuery<DB>)) -> bool {
+        match &self.fork {
+            Some(fork) if !self.local_state.query_in_progress() => {
+                op(&mut fork.reads.lock());
+                true
+            }
+            _ => false,
+        }
     }
 
-    /// Obviously, this should be user configurable at some point.
-    pub(crate) fn report_unexpected_cycle(&self, database_key: DB::DatabaseKey) -> ! {
-        debug!("report_unexpected_cycle(database_key={:?})", database_key);
+    /// True if `id` is this runtime or one that it was (transitively)
+    /// forked from. Blocking on such a runtime would deadlock: it is
+    /// waiting for us.
+    pub(crate) fn is_self_or_forked_from(&self, id: RuntimeId) -> bool {
+        id == self.id
+            || self
+                .fork
+                .as_ref()
+                .is_some_and(|fork| fork.ancestors.contains(&id))
+    }
+
+    /// Invoked when the query `database_key` was found to (transitively)
+    /// depend on itself. Marks each query on the stack that takes part
+    /// in the cycle and returns a description of the cycle, whose
+    /// participants start with `database_key`.
+    ///
+    /// If the cycle spans several runtimes (i.e., `error.to` is not
+    /// our own id), the participants from the other runtimes are
+    /// included as well and the same description is recorded for each
+    /// of those runtimes, so that they observe the cycle once they are
+    /// unblocked (see `take_blocked_cycle`).
+    pub(crate) fn mark_cycle_participants(
+        &self,
+        database_key: &DB::DatabaseKey,
+        error: CycleDetected,
+    ) -> Cycle<DB> {
+        debug!(
+            "mark_cycle_participants(database_key={:?}, from={:?}, to={:?})",
+            database_key, error.from, error.to
+        );
 
-        let query_stack = self.local_state.borrow_query_stack();
-        let start_index = (0..query_stack.len())
-            .rev()
-            .filter(|&i| query_stack[i].database_key == database_key)
-            .next()
-            .unwrap();
+        let stack = self.local_state.query_stack_keys();
 
-        let mut message = format!("Internal error, cycle detected:\n");
-        for active_query in &query_stack[start_index..] {
-            writeln!(message, "- {:?}\n", active_query.database_key).unwrap();
+        let cycle = if error.from == error.to {
+            // All queries in the cycle are on our own stack.
+            let mut cycle = Vec::new();
+            push_cycle_segment(&mut cycle, &stack, database_key);
+            Cycle::new(cycle)
+        } else {
+            // Part of the cycle is on other runtimes. Those are all
+            // (transitively) blocked on us, so their part of the
+            // dependency graph cannot change while we inspect it.
+            let mut dependency_graph = self.shared_state.dependency_graph.lock();
+            let (cycle, runtime_ids) =
+                dependency_graph.cycle(error.from, &stack, database_key, error.to);
+            let cycle = Cycle::new(cycle);
+            for runtime_id in runtime_ids {
+                dependency_graph.cycles.insert(runtime_id, cycle.clone());
+            }
+            cycle
+        };
+
+        self.local_state.mark_cycle_participants(&cycle);
+        cycle
+    }
+
+    /// Invoked when the query `database_key` was found to be part of
+    /// `cycle` and cannot recover from it. If another participant on
+    /// our stack can (see `#[salsa::cycle]`), unwinds up to that query
+    /// (see `catch_cycle`), so that whether a cycle is recovered from
+    /// does not depend on which participant was invoked first.
+    pub(crate) fn unwind_to_cycle_recovery(
+        &self,
+        cycle: &Cycle<DB>,
+        database_key: &DB::DatabaseKey,
+    ) {
+        if let Some(depth) =
+            self.local_state
+                .cycle_recovery_depth(cycle, database_key, self.current_revision())
+        {
+            debug!("unwind_to_cycle_recovery: unwinding to depth {}", depth);
+            std::panic::resume_unwind(Box::new(UnwindCycle { depth }));
         }
-        panic!(message)
     }
 
-    /// Try to make this runtime blocked on `other_id`. Returns true
-    /// upon success or false if `other_id` is already blocked on us.
-    pub(crate) fn try_block_on(&self, database_key: &DB::DatabaseKey, other_id: RuntimeId) -> bool {
-        self.shared_state
+    /// Invokes `execute`, the implementation of the active query, which
+    /// can recover from cycles. Returns `None` if it unwound because
+    /// another participant of a cycle could not recover from it (see
+    /// `unwind_to_cycle_recovery`).
+    pub(crate) fn catch_cycle<V>(&self, execute: impl FnOnce() -> V) -> Option<V> {
+        let depth = self.local_state.query_stack_len();
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(execute)) {
+            Ok(value) => Some(value),
+            Err(payload) => match payload.downcast::<UnwindCycle>() {
+                Ok(unwind) if unwind.depth == depth => None,
+                Ok(unwind) => std::panic::resume_unwind(unwind),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        }
+    }
+
+    /// Invoked after this runtime was unblocked (having been blocked
+    /// via `try_block_on`). If, while we were blocked, another runtime
+    /// found a cycle that we take part in, marks the participants on
+    /// our stack and returns the cycle.
+    pub(crate) fn take_blocked_cycle(&self) -> Option<Cycle<DB>> {
+        let cycle = self
+            .shared_state
             .dependency_graph
             .lock()
-            .add_edge(self.id(), database_key, other_id)
+            .cycles
+            .remove(&self.id())?;
+
+        debug!("take_blocked_cycle: {:?}", cycle);
+
+        self.local_state.mark_cycle_participants(&cycle);
+        Some(cycle)
+    }
+
+    /// Try to make this runtime blocked on `other_id`. Returns `Err`
+    /// if `other_id` is already (transitively) blocked on us.
+    pub(crate) fn try_block_on(
+        &self,
+        database_key: &DB::DatabaseKey,
+        other_id: RuntimeId,
+    ) -> Result<(), CycleDetected> {
+        let stack = self.local_state.query_stack_keys();
+        if self.shared_state.dependency_graph.lock().add_edge(
+            self.id(),
+            database_key,
+            other_id,
+            stack,
+        ) {
+            Ok(())
+        } else {
+            Err(CycleDetected {
+                from: self.id(),
+                to: other_id,
+            })
+        }
     }
 
     pub(crate) fn unblock_queries_blocked_on_self(&self, database_key: &DB::DatabaseKey) {
@@ -426,9 +922,35 @@ struct SharedState<DB: Database> {
     /// revision is canceled).
     pending_revision: AtomicUsize,
 
+    /// For each durability, the last revision in which an input with
+    /// at least that durability changed. (Since a change to a durable
+    /// input may affect values of lower durability, too, this is
+    /// monotonically decreasing with the durability.)
+    last_changed_revisions: [AtomicUsize; Durability::LEN],
+
     /// The dependency graph tracks which runtimes are blocked on one
     /// another, waiting for queries to terminate.
     dependency_graph: Mutex<DependencyGraph<DB>>,
+
+    /// Statistics about the executions of derived queries (if
+    /// enabled).
+    query_stats: QueryStatsCollector,
+
+    /// The snapshots that are alive, and hence hold a read-lock on
+    /// `query_lock`.
+    snapshots: Mutex<FxHashMap<RuntimeId, SnapshotInfo>>,
+
+    /// Whether to capture a backtrace when creating a snapshot; see
+    /// `Runtime::set_snapshot_backtraces`.
+    snapshot_backtraces: AtomicBool,
+
+    /// Whether derived queries check for cancellation automatically;
+    /// see `Runtime::set_automatic_cancellation`.
+    automatic_cancellation: AtomicBool,
+
+    /// The runtimes that were asked to unwind by a runtime of higher
+    /// priority; see `Runtime::preempt_if_lower_priority`.
+    preempted: Mutex<FxHashSet<RuntimeId>>,
 }
 
 impl<DB> std::panic::RefUnwindSafe for SharedState<DB>
@@ -446,7 +968,13 @@ impl<DB: Database> Default for SharedState<DB> {
             query_lock: Default::default(),
             revision: Default::default(),
             pending_revision: Default::default(),
+            last_changed_revisions: Default::default(),
             dependency_graph: Default::default(),
+            query_stats: Default::default(),
+            snapshots: Default::default(),
+            snapshot_backtraces: Default::default(),
+            automatic_cancellation: Default::default(),
+            preempted: Default::default(),
         }
     }
 }
@@ -471,6 +999,13 @@ where
     }
 }
 
+/// Panic payload with which a runtime unwinds to the query at `depth`
+/// on its stack, which recovers from the cycle that the queries above
+/// it take part in (see `Runtime::unwind_to_cycle_recovery`).
+struct UnwindCycle {
+    depth: usize,
+}
+
 struct ActiveQuery<DB: Database> {
     /// What query is executing
     database_key: DB::DatabaseKey,
@@ -484,6 +1019,18 @@ struct ActiveQuery<DB: Database> {
     /// Set of subqueries that were accessed thus far, or `None` if
     /// there was an untracked the read.
     subqueries: Option<FxIndexSet<DB::DatabaseKey>>,
+
+    /// If this query was found to be part of a cycle, the queries
+    /// participating in that cycle (otherwise empty).
+    cycle: Vec<DB::DatabaseKey>,
+
+    /// True if this query can recover from cycles (see
+    /// `#[salsa::cycle]`).
+    cycle_recovery: bool,
+
+    /// The reads of the queries invoked on forks of this query (see
+    /// `ParallelDatabase::fork`).
+    forks: Vec<Arc<Mutex<ActiveQuery<DB>>>>,
 }
 
 pub(crate) struct ComputedQueryResult<DB: Database, V> {
@@ -500,23 +1047,32 @@ pub(crate) struct ComputedQueryResult<DB: Database, V> {
     /// Complete set of subqueries that were accessed, or `None` if
     /// there was an untracked the read.
     pub(crate) subqueries: Option<FxIndexSet<DB::DatabaseKey>>,
+
+    /// The queries participating in the cycle this query was found
+    /// to be part of, if any (otherwise empty).
+    pub(crate) cycle: Vec<DB::DatabaseKey>,
 }
 
 impl<DB: Database> ActiveQuery<DB> {
-    fn new(database_key: DB::DatabaseKey) -> Self {
+    fn new(database_key: DB::DatabaseKey, cycle_recovery: bool) -> Self {
         ActiveQuery {
             database_key,
             changed_at: ChangedAt {
                 is_constant: true,
+                durability: Durability::High,
                 revision: Revision::ZERO,
             },
             subqueries: Some(FxIndexSet::default()),
+            cycle: Vec::new(),
+            cycle_recovery,
+            forks: Vec::new(),
         }
     }
 
     fn add_read(&mut self, subquery: &DB::DatabaseKey, changed_at: ChangedAt) {
         let ChangedAt {
             is_constant,
+            durability,
             revision,
         } = changed_at;
 
@@ -525,18 +1081,87 @@ impl<DB: Database> ActiveQuery<DB> {
         }
 
         self.changed_at.is_constant &= is_constant;
+        self.changed_at.durability = self.changed_at.durability.min(durability);
         self.changed_at.revision = self.changed_at.revision.max(revision);
     }
 
     fn add_untracked_read(&mut self, changed_at: Revision) {
         self.subqueries = None;
         self.changed_at.is_constant = false;
+        self.changed_at.durability = Durability::Low;
         self.changed_at.revision = changed_at;
     }
 
     fn add_anon_read(&mut self, changed_at: Revision) {
         self.changed_at.revision = self.changed_at.revision.max(changed_at);
     }
+
+    /// Adds the reads of the forks of this query to its own.
+    fn merge_forks(&mut self) {
+        for fork in std::mem::take(&mut self.forks) {
+            let fork = match Arc::try_unwrap(fork) {
+                Ok(fork) => fork.into_inner(),
+                Err(_) => panic!(
+                    "a snapshot forked by `{:?}` is still alive when it completes",
+                    self.database_key
+                ),
+            };
+
+            match (&mut self.subqueries, fork.subqueries) {
+                (Some(subqueries), Some(fork_subqueries)) => subqueries.extend(fork_subqueries),
+                (subqueries, _) => *subqueries = None,
+            }
+            self.changed_at.is_constant &= fork.changed_at.is_constant;
+            self.changed_at.durability = self.changed_at.durability.min(fork.changed_at.durability);
+            self.changed_at.revision = self.changed_at.revision.max(fork.changed_at.revision);
+        }
+    }
+}
+
+/// See `ParallelDatabase::fork`.
+struct Fork<DB: Database> {
+    /// The reads of the queries invoked on the forked runtime (outside
+    /// of any other query); they are added to those of the query that
+    /// forked it.
+    reads: Arc<Mutex<ActiveQuery<DB>>>,
+
+    /// The ids of the runtimes that this one was (transitively)
+    /// forked from.
+    ancestors: Vec<RuntimeId>,
+}
+
+// Reads are only ever added to `reads`, so it is fine to observe them
+// after a panic.
+impl<DB> std::panic::RefUnwindSafe for Fork<DB> where DB: Database {}
+
+/// Describes a snapshot that is alive; see `Runtime::live_snapshots`.
+#[derive(Clone, Debug)]
+pub struct SnapshotInfo {
+    /// The id of the snapshot's runtime.
+    pub id: RuntimeId,
+
+    /// The label given with `Runtime::set_snapshot_label`, if any.
+    pub label: Option<String>,
+
+    /// Where the snapshot was created, if backtraces were captured at
+    /// the time (see `Runtime::set_snapshot_backtraces`).
+    pub backtrace: Option<Arc<Backtrace>>,
+
+    /// The priority given with `Runtime::set_snapshot_priority`.
+    pub priority: Priority,
+}
+
+/// How urgently a runtime needs the results of its queries; see
+/// `Runtime::set_snapshot_priority`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    /// Work whose results nobody is waiting for, like analyzing a
+    /// whole project. It yields to interactive work.
+    Background,
+
+    /// Work that a user is waiting for, like a completion request.
+    #[default]
+    Interactive,
 }
 
 /// A unique identifier for a particular runtime. Each time you create
@@ -553,6 +1178,7 @@ pub struct RuntimeId {
 /// recomputed, but not something you should have to interact with
 /// directly as a user of salsa.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Revision {
     generation: u64,
 }
@@ -580,10 +1206,15 @@ impl std::fmt::Debug for Revision {
 
 /// Records when a stamped value changed.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChangedAt {
     // Will this value ever change again?
     pub(crate) is_constant: bool,
 
+    // How likely is this value to change? (For derived values, the
+    // lowest durability of their inputs.)
+    pub(crate) durability: Durability,
+
     // At which revision did this value last change? (If this value is
     // the value of a constant input, this indicates when it became
     // constant.)
@@ -600,18 +1231,55 @@ impl ChangedAt {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct StampedValue<V> {
     pub(crate) value: V,
     pub(crate) changed_at: ChangedAt,
 }
 
+/// Appends to `cycle` the participants of a cycle found on the query
+/// `stack` of a single runtime, where `database_key` is the query that
+/// was re-entered. If `database_key` is not on the stack (e.g., because
+/// it is being validated rather than executed), we conservatively
+/// treat the entire stack as part of the cycle.
+fn push_cycle_segment<K: Clone + Eq>(cycle: &mut Vec<K>, stack: &[K], database_key: &K) {
+    let start_index = stack.iter().rposition(|key| key == database_key);
+    if start_index.is_none() {
+        cycle.push(database_key.clone());
+    }
+    cycle.extend(stack[start_index.unwrap_or(0)..].iter().cloned());
+}
+
 struct DependencyGraph<DB: Database> {
     /// A `(K -> V)` pair in this map indicates that the the runtime
     /// `K` is blocked on some query executing in the runtime `V`.
     /// This encodes a graph that must be acyclic (or else deadlock
     /// will result).
-    edges: FxHashMap<RuntimeId, RuntimeId>,
+    edges: FxHashMap<RuntimeId, Edge<DB>>,
     labels: FxHashMap<DB::DatabaseKey, SmallVec<[RuntimeId; 4]>>,
+
+    /// A `(K -> (V, S))` pair in this map indicates that `K` is a fork
+    /// that the runtime `V` created while executing the queries `S`.
+    /// The query that created it cannot complete while the fork is
+    /// alive, so `V` effectively waits for `K`. Removed once the fork
+    /// is dropped.
+    forks: FxHashMap<RuntimeId, (RuntimeId, Vec<DB::DatabaseKey>)>,
+
+    /// Cycles found by another runtime that the (blocked) runtime in
+    /// the key takes part in. Removed by that runtime once it is
+    /// unblocked.
+    cycles: FxHashMap<RuntimeId, Cycle<DB>>,
+}
+
+struct Edge<DB: Database> {
+    /// The runtime that we are blocked on.
+    id: RuntimeId,
+
+    /// The query we are waiting for.
+    database_key: DB::DatabaseKey,
+
+    /// The query stack of the blocked runtime.
+    path: Vec<DB::DatabaseKey>,
 }
 
 impl<DB: Database> Default for DependencyGraph<DB> {
@@ -619,6 +1287,8 @@ impl<DB: Database> Default for DependencyGraph<DB> {
         DependencyGraph {
             edges: Default::default(),
             labels: Default::default(),
+            forks: Default::default(),
+            cycles: Default::default(),
         }
     }
 }
@@ -630,22 +1300,25 @@ impl<DB: Database> DependencyGraph<DB> {
         from_id: RuntimeId,
         database_key: &DB::DatabaseKey,
         to_id: RuntimeId,
+        path: Vec<DB::DatabaseKey>,
     ) -> bool {
         assert_ne!(from_id, to_id);
         debug_assert!(!self.edges.contains_key(&from_id));
 
-        // First: walk the chain of things that `to_id` depends on,
-        // looking for us.
-        let mut p = to_id;
-        while let Some(&q) = self.edges.get(&p) {
-            if q == from_id {
-                return false;
-            }
-
-            p = q;
+        // First: walk the runtimes that `to_id` (transitively) waits
+        // for, looking for us.
+        if self.path(to_id, from_id).is_some() {
+            return false;
         }
 
-        self.edges.insert(from_id, to_id);
+        self.edges.insert(
+            from_id,
+            Edge {
+                id: to_id,
+                database_key: database_key.clone(),
+                path,
+            },
+        );
         self.labels
             .entry(database_key.clone())
             .or_insert(SmallVec::default())
@@ -660,21 +1333,110 @@ impl<DB: Database> DependencyGraph<DB> {
             .unwrap_or(SmallVec::default());
 
         for from_id in &vec {
-            let to_id1 = self.edges.remove(from_id);
+            let to_id1 = self.edges.remove(from_id).map(|edge| edge.id);
             assert_eq!(Some(to_id), to_id1);
         }
     }
+
+    /// Invoked when `from_id` (whose query stack is `from_stack`)
+    /// attempted to block on `database_key`, which is being computed
+    /// by `to_id`, but `to_id` is (transitively) blocked on `from_id`.
+    /// Returns the participants of the cycle, starting with
+    /// `database_key`, along with the ids of the other runtimes that
+    /// take part in it.
+    fn cycle(
+        &self,
+        from_id: RuntimeId,
+        from_stack: &[DB::DatabaseKey],
+        database_key: &DB::DatabaseKey,
+        to_id: RuntimeId,
+    ) -> (Vec<DB::DatabaseKey>, Vec<RuntimeId>) {
+        let mut cycle = Vec::new();
+        let mut runtime_ids = Vec::new();
+
+        // The query that the current runtime waits for, or `None` if
+        // it waits for a fork, which is executing on behalf of all the
+        // queries on its stack.
+        let mut waited_on = Some(database_key);
+        let push_segment = |cycle: &mut Vec<_>, stack: &[_], waited_on| match waited_on {
+            Some(waited_on) => push_cycle_segment(cycle, stack, waited_on),
+            None => cycle.extend(stack.iter().cloned()),
+        };
+
+        let mut id = to_id;
+        for next_id in self.path(to_id, from_id).unwrap() {
+            match self.edges.get(&id) {
+                Some(edge) if edge.id == next_id => {
+                    push_segment(&mut cycle, &edge.path, waited_on);
+                    runtime_ids.push(id);
+                    waited_on = Some(&edge.database_key);
+                }
+                _ => {
+                    // `id` is not blocked, but waits for its fork.
+                    let (_, stack) = &self.forks[&next_id];
+                    push_segment(&mut cycle, stack, waited_on);
+                    waited_on = None;
+                }
+            }
+            id = next_id;
+        }
+        push_segment(&mut cycle, from_stack, waited_on);
+
+        (cycle, runtime_ids)
+    }
+
+    /// The runtimes that `id` waits for: the one it is blocked on, if
+    /// any, and its forks.
+    fn waits_for(&self, id: RuntimeId) -> impl Iterator<Item = RuntimeId> + '_ {
+        let blocked_on = self.edges.get(&id).map(|edge| edge.id);
+        let forks = self
+            .forks
+            .iter()
+            .filter(move |(_, (parent_id, _))| *parent_id == id)
+            .map(|(fork_id, _)| *fork_id);
+        blocked_on.into_iter().chain(forks)
+    }
+
+    /// If `from_id` (transitively) waits for `to_id`, returns the
+    /// runtimes in between, followed by `to_id`, such that each
+    /// runtime waits for the next.
+    fn path(&self, from_id: RuntimeId, to_id: RuntimeId) -> Option<Vec<RuntimeId>> {
+        let mut predecessors = FxHashMap::default();
+        let mut pending = vec![from_id];
+        while let Some(id) = pending.pop() {
+            for next_id in self.waits_for(id) {
+                if predecessors.contains_key(&next_id) {
+                    continue;
+                }
+                predecessors.insert(next_id, id);
+
+                if next_id == to_id {
+                    let mut path = vec![to_id];
+                    let mut id = id;
+                    while id != from_id {
+                        path.push(id);
+                        id = predecessors[&id];
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                pending.push(next_id);
+            }
+        }
+        None
+    }
 }
 
 struct RevisionGuard<DB: Database> {
     shared_state: Arc<SharedState<DB>>,
+    id: RuntimeId,
 }
 
 impl<DB> RevisionGuard<DB>
 where
     DB: Database,
 {
-    fn new(shared_state: &Arc<SharedState<DB>>) -> Self {
+    fn new(shared_state: &Arc<SharedState<DB>>, id: RuntimeId, priority: Priority) -> Self {
         // Subtle: we use a "recursive" lock here so that it is not an
         // error to acquire a read-lock when one is already held (this
         // happens when a query uses `snapshot` to spawn off parallel
@@ -695,8 +1457,24 @@ where
             shared_state.query_lock.raw().lock_shared_recursive();
         }
 
+        let backtrace = if shared_state.snapshot_backtraces.load(Ordering::Relaxed) {
+            Some(Arc::new(Backtrace::force_capture()))
+        } else {
+            None
+        };
+        shared_state.snapshots.lock().insert(
+            id,
+            SnapshotInfo {
+                id,
+                label: None,
+                backtrace,
+                priority,
+            },
+        );
+
         Self {
             shared_state: shared_state.clone(),
+            id,
         }
     }
 }
@@ -706,6 +1484,14 @@ where
     DB: Database,
 {
     fn drop(&mut self) {
+        self.shared_state.snapshots.lock().remove(&self.id);
+        self.shared_state.preempted.lock().remove(&self.id);
+        self.shared_state
+            .dependency_graph
+            .lock()
+            .forks
+            .remove(&self.id);
+
         // Release our read-lock without using RAII. As documented in
         // `Snapshot::new` above, this requires the unsafe keyword.
         unsafe {
@@ -713,3 +1499,38 @@ where
         }
     }
 }
+
+/// Increments the revision to the new one, recording it as the last
+/// one in which inputs of `durability` (and all lower ones) changed,
+/// once the `op` of `increment_revision` is done -- also if it panics,
+/// as it may have changed inputs before. It is not known which inputs
+/// those were, so `durability` remains `High` in that case. If `op`
+/// changed no input (`durability` is `None`), the revision is left
+/// as it was, and only the cancellation is withdrawn.
+struct LastChangedGuard<'me, DB: Database> {
+    shared_state: &'me SharedState<DB>,
+    revision: Revision,
+    durability: Option<Durability>,
+}
+
+impl<DB> Drop for LastChangedGuard<'_, DB>
+where
+    DB: Database,
+{
+    fn drop(&mut self) {
+        let shared_state = self.shared_state;
+        let durability = match self.durability {
+            Some(durability) => durability,
+            None => {
+                debug!("increment_revision: nothing changed");
+                shared_state.pending_revision.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+        };
+
+        shared_state.revision.fetch_add(1, Ordering::SeqCst);
+        for last_changed in &shared_state.last_changed_revisions[..=durability.index()] {
+            last_changed.store(self.revision.as_usize(), Ordering::SeqCst);
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use crate::runtime::ActiveQuery;
 use crate::runtime::ChangedAt;
 use crate::runtime::Revision;
+use crate::Cycle;
 use crate::Database;
+use crate::Durability;
+use parking_lot::Mutex;
+use std::cell::Cell;
 use std::cell::Ref;
 use std::cell::RefCell;
+use std::sync::Arc;
 
 /// State that is specific to a single execution thread.
 ///
@@ -17,20 +22,48 @@ pub(super) struct LocalState<DB: Database> {
     /// Unwinding note: pushes onto this vector must be popped -- even
     /// during unwinding.
     query_stack: RefCell<Vec<ActiveQuery<DB>>>,
+
+    /// If a transaction is open, the revision it created and the
+    /// highest durability of the inputs it changed so far (`None` if
+    /// it changed none yet).
+    ///
+    /// Unwinding note: must be reset to `None` -- even during
+    /// unwinding.
+    transaction: Cell<Option<(Revision, Option<Durability>)>>,
+
+    /// True while `ParallelDatabase::fork` creates a snapshot.
+    ///
+    /// Unwinding note: must be reset to `false` -- even during
+    /// unwinding.
+    forking: Cell<bool>,
+
+    /// True while this runtime unwinds because a runtime of higher
+    /// priority asked it to; see `Runtime::unwind_if_preempted`.
+    ///
+    /// Unwinding note: reset to `false` once the outermost query has
+    /// unwound (see `take_preempted`).
+    preempted: Cell<bool>,
 }
 
 impl<DB: Database> Default for LocalState<DB> {
     fn default() -> Self {
         LocalState {
             query_stack: Default::default(),
+            transaction: Default::default(),
+            forking: Default::default(),
+            preempted: Default::default(),
         }
     }
 }
 
 impl<DB: Database> LocalState<DB> {
-    pub(super) fn push_query(&self, database_key: &DB::DatabaseKey) -> ActiveQueryGuard<'_, DB> {
+    pub(super) fn push_query(
+        &self,
+        database_key: &DB::DatabaseKey,
+        cycle_recovery: bool,
+    ) -> ActiveQueryGuard<'_, DB> {
         let mut query_stack = self.query_stack.borrow_mut();
-        query_stack.push(ActiveQuery::new(database_key.clone()));
+        query_stack.push(ActiveQuery::new(database_key.clone(), cycle_recovery));
         ActiveQueryGuard {
             local_state: self,
             push_len: query_stack.len(),
@@ -46,6 +79,62 @@ impl<DB: Database> LocalState<DB> {
         self.query_stack.borrow()
     }
 
+    /// Returns the database-keys of the active queries, starting
+    /// with the outermost one.
+    pub(super) fn query_stack_keys(&self) -> Vec<DB::DatabaseKey> {
+        self.query_stack
+            .borrow()
+            .iter()
+            .map(|active_query| active_query.database_key.clone())
+            .collect()
+    }
+
+    /// Records `cycle` on each active query that participates in it.
+    pub(super) fn mark_cycle_participants(&self, cycle: &Cycle<DB>) {
+        for active_query in self
+            .query_stack
+            .borrow_mut()
+            .iter_mut()
+            .filter(|active_query| cycle.participants().contains(&active_query.database_key))
+        {
+            active_query.cycle = cycle.participants().to_vec();
+        }
+    }
+
+    /// Invoked when the query `database_key` was found to be part of
+    /// `cycle` and cannot recover from it. Looks for the innermost
+    /// participant on the stack that can recover; if there is one,
+    /// records its read of the query it was executing (which will not
+    /// complete) and returns its depth on the stack.
+    pub(super) fn cycle_recovery_depth(
+        &self,
+        cycle: &Cycle<DB>,
+        database_key: &DB::DatabaseKey,
+        revision: Revision,
+    ) -> Option<usize> {
+        let mut query_stack = self.query_stack.borrow_mut();
+        let index = query_stack.iter().rposition(|active_query| {
+            active_query.cycle_recovery && cycle.participants().contains(&active_query.database_key)
+        })?;
+        let read = query_stack
+            .get(index + 1)
+            .map_or(database_key, |active_query| &active_query.database_key)
+            .clone();
+        query_stack[index].add_read(
+            &read,
+            ChangedAt {
+                is_constant: false,
+                durability: Durability::Low,
+                revision,
+            },
+        );
+        Some(index + 1)
+    }
+
+    pub(super) fn query_stack_len(&self) -> usize {
+        self.query_stack.borrow().len()
+    }
+
     pub(super) fn query_in_progress(&self) -> bool {
         !self.query_stack.borrow().is_empty()
     }
@@ -57,6 +146,77 @@ impl<DB: Database> LocalState<DB> {
             .map(|active_query| active_query.database_key.clone())
     }
 
+    /// Invokes `op`, during which `is_forking` returns true.
+    pub(super) fn with_forking<T>(&self, op: impl FnOnce() -> T) -> T {
+        struct Reset<'me>(&'me Cell<bool>, bool);
+
+        impl Drop for Reset<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.1);
+            }
+        }
+
+        let _reset = Reset(&self.forking, self.forking.replace(true));
+        op()
+    }
+
+    pub(super) fn is_forking(&self) -> bool {
+        self.forking.get()
+    }
+
+    pub(super) fn set_preempted(&self) {
+        self.preempted.set(true);
+    }
+
+    /// Returns whether this runtime is unwinding because it was
+    /// preempted; resets that once no query is in progress anymore.
+    pub(super) fn take_preempted(&self) -> bool {
+        let preempted = self.preempted.get();
+        if !self.query_in_progress() {
+            self.preempted.set(false);
+        }
+        preempted
+    }
+
+    /// Creates the reads of a fork of the active query, which are
+    /// added to those of the active query once it completes.
+    pub(super) fn add_fork(&self) -> Arc<Mutex<ActiveQuery<DB>>> {
+        let mut query_stack = self.query_stack.borrow_mut();
+        let active_query = query_stack.last_mut().unwrap();
+        let fork = Arc::new(Mutex::new(ActiveQuery::new(
+            active_query.database_key.clone(),
+            false,
+        )));
+        active_query.forks.push(fork.clone());
+        fork
+    }
+
+    /// Opens a transaction that applies its changes in `revision`;
+    /// returns a guard that closes it again.
+    pub(super) fn open_transaction(&self, revision: Revision) -> TransactionGuard<'_, DB> {
+        assert!(
+            self.transaction.get().is_none(),
+            "transactions cannot be nested"
+        );
+        self.transaction.set(Some((revision, None)));
+        TransactionGuard { local_state: self }
+    }
+
+    /// Returns the revision of the open transaction, if any.
+    pub(super) fn transaction_revision(&self) -> Option<Revision> {
+        self.transaction.get().map(|(revision, _)| revision)
+    }
+
+    /// Records that the open transaction changed an input of
+    /// `durability`.
+    pub(super) fn record_transaction_change(&self, durability: Durability) {
+        if let Some((revision, changed_durability)) = self.transaction.get() {
+            let durability =
+                changed_durability.map_or(durability, |changed| changed.max(durability));
+            self.transaction.set(Some((revision, Some(durability))));
+        }
+    }
+
     pub(super) fn report_query_read(&self, database_key: &DB::DatabaseKey, changed_at: ChangedAt) {
         if let Some(top_query) = self.query_stack.borrow_mut().last_mut() {
             top_query.add_read(database_key, changed_at);
@@ -78,6 +238,34 @@ impl<DB: Database> LocalState<DB> {
 
 impl<DB> std::panic::RefUnwindSafe for LocalState<DB> where DB: Database {}
 
+/// Represents an open transaction; see `LocalState::open_transaction`.
+/// The guard's destructor closes the transaction, also in the case of
+/// unwinding.
+pub(super) struct TransactionGuard<'me, DB: Database> {
+    local_state: &'me LocalState<DB>,
+}
+
+impl<DB> TransactionGuard<'_, DB>
+where
+    DB: Database,
+{
+    /// Closes the transaction, returning the highest durability of the
+    /// inputs that it changed, or `None` if it changed none.
+    pub(super) fn close(self) -> Option<Durability> {
+        let (_, durability) = self.local_state.transaction.take().unwrap();
+        durability
+    }
+}
+
+impl<DB> Drop for TransactionGuard<'_, DB>
+where
+    DB: Database,
+{
+    fn drop(&mut self) {
+        self.local_state.transaction.set(None);
+    }
+}
+
 /// When a query is pushed onto the `active_query` stack, this guard
 /// is returned to represent its slot. The guard can be used to pop
 /// the query from the stack -- in the case of unwinding, the guard's
@@ -103,6 +291,9 @@ where
     /// Invoked when the query has successfully completed execution.
     pub(super) fn complete(self) -> ActiveQuery<DB> {
         let query = self.pop_helper();
+        if !self.local_state.query_in_progress() {
+            self.local_state.preempted.set(false);
+        }
         std::mem::forget(self);
         query
     }
@@ -0,0 +1,87 @@
+use parking_lot::Mutex;
+use rustc_hash::FxHashMap;
+use std::any::TypeId;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Statistics collected for the derived query `query`, while the
+/// collection was enabled; see `Runtime::set_query_stats_enabled`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    /// The name of the query type (e.g., `MyQuery`).
+    pub query: String,
+
+    /// How often the query function was executed.
+    pub executions: u64,
+
+    /// How often a memoized value was returned that was already
+    /// verified in the current revision.
+    pub hits: u64,
+
+    /// How often a memoized value from an earlier revision was
+    /// returned after verifying that none of its inputs changed.
+    pub validations: u64,
+
+    /// How often a thread blocked on another thread that was
+    /// computing the same value.
+    pub blocked: u64,
+
+    /// Total time spent executing the query function. This includes
+    /// the time spent in the queries it invoked.
+    pub execution_time: Duration,
+}
+
+/// Collects the `QueryStats` of each query type, if enabled; shared
+/// between all runtimes of a database.
+#[derive(Default)]
+pub(crate) struct QueryStatsCollector {
+    enabled: AtomicBool,
+    stats: Mutex<FxHashMap<TypeId, QueryStats>>,
+}
+
+impl QueryStatsCollector {
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Updates the statistics of the query `Q` with `op`, if the
+    /// collection is enabled.
+    pub(crate) fn record<Q>(&self, op: impl FnOnce(&mut QueryStats))
+    where
+        Q: Debug + Default + 'static,
+    {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut stats = self.stats.lock();
+        let query_stats = stats
+            .entry(TypeId::of::<Q>())
+            .or_insert_with(|| QueryStats {
+                query: format!("{:?}", Q::default()),
+                ..QueryStats::default()
+            });
+        op(query_stats);
+    }
+
+    /// Returns the statistics of all queries, those with the largest
+    /// total execution time first.
+    pub(crate) fn report(&self) -> Vec<QueryStats> {
+        let mut report: Vec<QueryStats> = self.stats.lock().values().cloned().collect();
+        report.sort_by(|a, b| {
+            b.execution_time
+                .cmp(&a.execution_time)
+                .then_with(|| a.query.cmp(&b.query))
+        });
+        report
+    }
+
+    pub(crate) fn reset(&self) {
+        self.stats.lock().clear();
+    }
+}
@@ -10,8 +10,37 @@ impl salsa::Database for DatabaseImpl {
     }
 }
 
+/// Gives `cycle_f` and `cycle_j` a way to observe their cycles through
+/// `try_get` (which requires the concrete database type).
+trait TryCycle {
+    fn try_cycle_e(&self) -> Result<(), Vec<String>>;
+    fn try_cycle_i(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+impl TryCycle for DatabaseImpl {
+    fn try_cycle_e(&self) -> Result<(), Vec<String>> {
+        use salsa::Database;
+
+        self.query(CycleEQuery).try_get(()).unwrap_or_else(|cycle| {
+            Err(cycle
+                .participants()
+                .iter()
+                .map(|k| format!("{:?}", k))
+                .collect())
+        })
+    }
+
+    fn try_cycle_i(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        use salsa::Database;
+
+        // `?` converts the `Cycle` into a boxed error
+        let result = self.query(CycleIQuery).try_get(())?;
+        Ok(result?)
+    }
+}
+
 #[salsa::query_group(GroupStruct)]
-trait Database: salsa::Database {
+trait Database: salsa::Database + TryCycle {
     // `a` and `b` depend on each other and form a cycle
     fn memoized_a(&self) -> ();
     fn memoized_b(&self) -> ();
@@ -19,6 +48,47 @@ trait Database: salsa::Database {
     fn volatile_a(&self) -> ();
     #[salsa::volatile]
     fn volatile_b(&self) -> ();
+
+    // `cycle_a` and `cycle_b` form a cycle but both can recover from it
+    #[salsa::cycle(recover)]
+    fn cycle_a(&self) -> Vec<String>;
+    #[salsa::cycle(recover)]
+    fn cycle_b(&self) -> Vec<String>;
+
+    // `cycle_c` and `cycle_d` form a cycle but only `cycle_c` can
+    // recover from it
+    #[salsa::cycle(recover)]
+    fn cycle_c(&self) -> Vec<String>;
+    fn cycle_d(&self) -> Vec<String>;
+
+    // `cycle_e` and `cycle_f` form a cycle, which `cycle_f` observes
+    // using `try_get`
+    fn cycle_e(&self) -> Result<(), Vec<String>>;
+    fn cycle_f(&self) -> Result<(), Vec<String>>;
+
+    // `cycle_g` and `cycle_h` form a cycle as long as `cycle_flag` is set
+    #[salsa::input]
+    fn cycle_flag(&self) -> bool;
+    #[salsa::cycle(recover_g)]
+    fn cycle_g(&self) -> u32;
+    fn cycle_h(&self) -> u32;
+
+    // `cycle_i` and `cycle_j` form a cycle, which `cycle_j` observes
+    // as an error
+    fn cycle_i(&self) -> Result<(), String>;
+    fn cycle_j(&self) -> Result<(), String>;
+}
+
+fn recover<DB: Database>(_db: &DB, cycle: &salsa::Cycle<DB>) -> Vec<String> {
+    cycle
+        .participants()
+        .iter()
+        .map(|database_key| format!("{:?}", database_key))
+        .collect()
+}
+
+fn recover_g<DB: Database>(_db: &DB, _cycle: &salsa::Cycle<DB>) -> u32 {
+    99
 }
 
 fn memoized_a(db: &impl Database) -> () {
@@ -37,6 +107,52 @@ fn volatile_b(db: &impl Database) -> () {
     db.volatile_a()
 }
 
+fn cycle_a(db: &impl Database) -> Vec<String> {
+    db.cycle_b()
+}
+
+fn cycle_b(db: &impl Database) -> Vec<String> {
+    db.cycle_a()
+}
+
+fn cycle_c(db: &impl Database) -> Vec<String> {
+    db.cycle_d()
+}
+
+fn cycle_d(db: &impl Database) -> Vec<String> {
+    let mut result = db.cycle_c();
+    result.push("cycle_d".to_string());
+    result
+}
+
+fn cycle_e(db: &impl Database) -> Result<(), Vec<String>> {
+    db.cycle_f()
+}
+
+fn cycle_f(db: &impl Database) -> Result<(), Vec<String>> {
+    db.try_cycle_e()
+}
+
+fn cycle_g(db: &impl Database) -> u32 {
+    if db.cycle_flag() {
+        db.cycle_h()
+    } else {
+        0
+    }
+}
+
+fn cycle_h(db: &impl Database) -> u32 {
+    db.cycle_g() + 1
+}
+
+fn cycle_i(db: &impl Database) -> Result<(), String> {
+    db.cycle_j()
+}
+
+fn cycle_j(db: &impl Database) -> Result<(), String> {
+    db.try_cycle_i().map_err(|error| error.to_string())
+}
+
 #[test]
 #[should_panic(expected = "cycle detected")]
 fn cycle_memoized() {
@@ -50,3 +166,75 @@ fn cycle_volatile() {
     let query = DatabaseImpl::default();
     query.volatile_a();
 }
+
+#[test]
+fn cycle_recovered() {
+    let query = DatabaseImpl::default();
+    let cycle = query.cycle_a();
+    assert_eq!(cycle.len(), 2);
+    assert!(cycle[0].contains("cycle_a"));
+    assert!(cycle[1].contains("cycle_b"));
+
+    // `cycle_b` was a participant too, so it uses its fallback value
+    assert_eq!(query.cycle_b(), cycle);
+}
+
+#[test]
+fn cycle_recovered_by_reentered_query() {
+    let query = DatabaseImpl::default();
+    let cycle = query.cycle_c();
+    assert_eq!(cycle.len(), 2);
+    assert!(cycle[0].contains("cycle_c"));
+    assert!(cycle[1].contains("cycle_d"));
+
+    // `cycle_d` cannot recover, so it builds on the fallback value of `cycle_c`
+    let mut expected = cycle.clone();
+    expected.push("cycle_d".to_string());
+    assert_eq!(query.cycle_d(), expected);
+}
+
+#[test]
+fn cycle_recovered_by_other_participant() {
+    let query = DatabaseImpl::default();
+    // `cycle_d` cannot recover, so `cycle_c` uses its fallback value
+    let result = query.cycle_d();
+    assert_eq!(result.len(), 3);
+    assert!(result[0].contains("cycle_d"));
+    assert!(result[1].contains("cycle_c"));
+    assert_eq!(result[2], "cycle_d");
+    assert_eq!(query.cycle_c(), result[..2]);
+}
+
+#[test]
+fn cycle_removed() {
+    let mut query = DatabaseImpl::default();
+    query.set_cycle_flag(true);
+    assert_eq!(query.cycle_g(), 99);
+    assert_eq!(query.cycle_h(), 100);
+
+    // The recovered value of `cycle_g` that `cycle_h` read may change
+    // with any input, so `cycle_h` is re-executed
+    query.set_cycle_flag(false);
+    assert_eq!(query.cycle_g(), 0);
+    assert_eq!(query.cycle_h(), 1);
+}
+
+#[test]
+fn cycle_observed_with_try_get() {
+    let query = DatabaseImpl::default();
+    let cycle = query.cycle_e().unwrap_err();
+    assert_eq!(cycle.len(), 2);
+    assert!(cycle[0].contains("cycle_e"));
+    assert!(cycle[1].contains("cycle_f"));
+}
+
+#[test]
+fn cycle_is_an_error() {
+    let query = DatabaseImpl::default();
+    let message = query.cycle_i().unwrap_err();
+    let mut lines = message.lines();
+    assert_eq!(lines.next(), Some("cycle detected:"));
+    assert!(lines.next().unwrap().contains("cycle_i"));
+    assert!(lines.next().unwrap().contains("cycle_j"));
+    assert_eq!(lines.next(), None);
+}
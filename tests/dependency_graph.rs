@@ -0,0 +1,151 @@
+//! Test that `salsa::debug::dependency_graph` reports the inputs of
+//! each memoized query.
+
+use salsa::Database;
+use std::collections::HashMap;
+
+#[salsa::query_group(GraphStorage)]
+trait GraphDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, x: u32) -> u32;
+
+    fn double(&self, x: u32) -> u32;
+
+    fn sum(&self) -> u32;
+
+    #[salsa::volatile]
+    fn volatile(&self) -> u32;
+
+    fn opaque(&self, key: Opaque) -> u32;
+}
+
+/// A key whose values all have the same `Debug` output.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Opaque(u32);
+
+impl std::fmt::Debug for Opaque {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "Opaque")
+    }
+}
+
+fn double(db: &impl GraphDatabase, x: u32) -> u32 {
+    db.input(x) * 2
+}
+
+fn sum(db: &impl GraphDatabase) -> u32 {
+    db.double(0) + db.double(1)
+}
+
+fn volatile(_db: &impl GraphDatabase) -> u32 {
+    0
+}
+
+fn opaque(db: &impl GraphDatabase, key: Opaque) -> u32 {
+    db.input(key.0)
+}
+
+#[salsa::database(GraphStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+/// Returns the nodes of `dot`, and its edges as `from -> to`, with
+/// each node replaced by the first line of its label.
+fn nodes_and_edges(dot: &str) -> (Vec<String>, Vec<String>) {
+    let mut lines: Vec<&str> = dot.lines().collect();
+    assert_eq!(lines.remove(0), "digraph salsa {");
+    assert_eq!(lines.pop(), Some("}"));
+
+    let mut labels = HashMap::new();
+    let mut nodes = Vec::new();
+    for line in &lines {
+        if let Some(index) = line.find(" [label=\"") {
+            let id = line[..index].trim();
+            let label = &line[index + " [label=\"".len()..line.len() - "\"];".len()];
+            assert!(labels
+                .insert(id, label.split("\\n").next().unwrap())
+                .is_none());
+            nodes.push(label.to_string());
+        }
+    }
+    let mut edges = Vec::new();
+    for line in &lines {
+        if let Some(index) = line.find(" -> ") {
+            let from = line[..index].trim();
+            let to = line[index + " -> ".len()..].trim_end_matches(';');
+            edges.push(format!("{} -> {}", labels[from], labels[to]));
+        }
+    }
+    nodes.sort();
+    edges.sort();
+    (nodes, edges)
+}
+
+#[test]
+fn dependency_graph() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0, 1);
+    db.set_input(1, 2);
+    assert_eq!(db.sum(), 6);
+    db.set_input(1, 3);
+    assert_eq!(db.sum(), 8);
+    assert_eq!(db.volatile(), 0);
+
+    let (nodes, edges) = nodes_and_edges(&salsa::debug::dependency_graph(&db));
+    assert_eq!(
+        nodes,
+        vec![
+            r#"DoubleQuery(0)\nverified_at: R3\nchanged_at: R1"#,
+            r#"DoubleQuery(1)\nverified_at: R3\nchanged_at: R3"#,
+            r#"InputQuery(0)\nchanged_at: R1"#,
+            r#"InputQuery(1)\nchanged_at: R3"#,
+            r#"SumQuery(())\nverified_at: R3\nchanged_at: R3"#,
+            r#"VolatileQuery(())\nverified_at: R3\nchanged_at: R3\nuntracked"#,
+        ]
+    );
+    assert_eq!(
+        edges,
+        vec![
+            "DoubleQuery(0) -> InputQuery(0)",
+            "DoubleQuery(1) -> InputQuery(1)",
+            "SumQuery(()) -> DoubleQuery(0)",
+            "SumQuery(()) -> DoubleQuery(1)",
+        ]
+    );
+}
+
+/// Test that keys with the same `Debug` output are distinct nodes.
+#[test]
+fn same_debug_output() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0, 1);
+    db.set_input(1, 2);
+    assert_eq!(db.opaque(Opaque(0)), 1);
+    assert_eq!(db.opaque(Opaque(1)), 2);
+
+    let (nodes, edges) = nodes_and_edges(&salsa::debug::dependency_graph(&db));
+    assert_eq!(
+        nodes,
+        vec![
+            r#"InputQuery(0)\nchanged_at: R1"#,
+            r#"InputQuery(1)\nchanged_at: R2"#,
+            r#"OpaqueQuery(Opaque)\nverified_at: R2\nchanged_at: R1"#,
+            r#"OpaqueQuery(Opaque)\nverified_at: R2\nchanged_at: R2"#,
+        ]
+    );
+    assert_eq!(
+        edges,
+        vec![
+            "OpaqueQuery(Opaque) -> InputQuery(0)",
+            "OpaqueQuery(Opaque) -> InputQuery(1)",
+        ]
+    );
+}
@@ -0,0 +1,22 @@
+use std::cell::Cell;
+
+pub(crate) trait HasCounter {
+    fn counter(&self) -> &Counter;
+}
+
+/// Counts the executions of queries.
+#[derive(Default)]
+pub(crate) struct Counter {
+    value: Cell<usize>,
+}
+
+impl Counter {
+    pub(crate) fn increment(&self) {
+        self.value.set(self.value.get() + 1);
+    }
+
+    /// Returns the number of executions so far, and starts over.
+    pub(crate) fn take(&self) -> usize {
+        self.value.replace(0)
+    }
+}
@@ -0,0 +1,98 @@
+use crate::log::{HasLog, Log};
+use salsa::{Database, Durability, EventKind};
+
+#[salsa::query_group(DurabilityStorage)]
+trait DurabilityDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn inner(&self, key: u32) -> u32;
+
+    fn outer(&self, key: u32) -> u32;
+}
+
+fn inner(db: &impl DurabilityDatabase, key: u32) -> u32 {
+    db.input(key)
+}
+
+fn outer(db: &impl DurabilityDatabase, key: u32) -> u32 {
+    db.inner(key) * 2
+}
+
+#[salsa::database(DurabilityStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+
+    fn salsa_event(&self, event_fn: impl Fn() -> salsa::Event<Self>) {
+        match event_fn().kind {
+            EventKind::DidValidateMemoizedValue { database_key } => {
+                self.log.add(format!("validated {:?}", database_key))
+            }
+            EventKind::WillExecute { database_key, .. } => {
+                self.log.add(format!("executed {:?}", database_key))
+            }
+            _ => {}
+        }
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn high_durability_skips_revalidation() {
+    let mut db = DatabaseImpl::default();
+    db.set_input_with_durability(1, 10, Durability::High);
+    db.set_input(2, 20);
+
+    assert_eq!(db.outer(1), 20);
+    assert_eq!(db.outer(2), 40);
+    db.log().take();
+
+    // Changing a low durability input does not require walking the
+    // inputs of `outer(1)`, so `inner(1)` is never validated.
+    db.set_input(2, 21);
+    assert_eq!(db.outer(1), 20);
+    let log = db.log().take();
+    assert_eq!(log.len(), 1, "{:#?}", log);
+    assert!(log[0].starts_with("validated") && log[0].contains("outer"));
+
+    // `outer(2)` depends on a low durability input, so it is re-executed.
+    assert_eq!(db.outer(2), 42);
+}
+
+#[test]
+fn high_durability_input_changes() {
+    let mut db = DatabaseImpl::default();
+    db.set_input_with_durability(1, 10, Durability::High);
+    assert_eq!(db.outer(1), 20);
+
+    db.set_input_with_durability(1, 11, Durability::High);
+    assert_eq!(db.outer(1), 22);
+}
+
+#[test]
+fn lowering_durability_invalidates() {
+    let mut db = DatabaseImpl::default();
+    db.set_input_with_durability(1, 10, Durability::High);
+    assert_eq!(db.outer(1), 20);
+
+    // Changing the input to low durability is still a change that
+    // values depending on it with high durability must observe.
+    db.set_input(1, 11);
+    assert_eq!(db.outer(1), 22);
+
+    db.set_input(1, 12);
+    assert_eq!(db.outer(1), 24);
+}
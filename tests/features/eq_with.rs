@@ -0,0 +1,111 @@
+//! Test that `#[salsa::eq_with]` and `#[salsa::no_eq]` decide whether a
+//! recomputed value changed.
+
+use crate::log::{HasLog, Log};
+use salsa::Database;
+
+/// An identifier, along with the position where it was found.
+#[derive(Clone, Debug)]
+struct Ident {
+    name: String,
+    offset: usize,
+}
+
+fn same_name(old_value: &Ident, new_value: &Ident) -> bool {
+    old_value.name == new_value.name
+}
+
+#[salsa::query_group(ParseStorage)]
+trait ParseDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn text(&self) -> String;
+
+    #[salsa::eq_with(same_name)]
+    fn ident(&self) -> Ident;
+
+    fn ident_len(&self) -> usize;
+
+    #[salsa::no_eq]
+    fn ratio(&self) -> f64;
+
+    fn ratio_rounded(&self) -> u64;
+}
+
+fn ident(db: &impl ParseDatabase) -> Ident {
+    db.log().add("ident");
+    let text = db.text();
+    let offset = text.len() - text.trim_start().len();
+    Ident {
+        name: text.trim().to_string(),
+        offset,
+    }
+}
+
+fn ident_len(db: &impl ParseDatabase) -> usize {
+    db.log().add("ident_len");
+    db.ident().name.len()
+}
+
+fn ratio(db: &impl ParseDatabase) -> f64 {
+    db.log().add("ratio");
+    db.text().len() as f64 / 2.0
+}
+
+fn ratio_rounded(db: &impl ParseDatabase) -> u64 {
+    db.log().add("ratio_rounded");
+    db.ratio().round() as u64
+}
+
+#[salsa::database(ParseStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn eq_with() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("foo".to_string());
+    assert_eq!(db.ident_len(), 3);
+    assert_eq!(db.log().take(), vec!["ident_len", "ident"]);
+
+    // Moving the identifier does not change it, according to
+    // `same_name`.
+    db.set_text("  foo".to_string());
+    assert_eq!(db.ident_len(), 3);
+    assert_eq!(db.log().take(), vec!["ident"]);
+
+    // The new value is memoized, though.
+    assert_eq!(db.ident().offset, 2);
+
+    db.set_text("  fooo".to_string());
+    assert_eq!(db.ident_len(), 4);
+    assert_eq!(db.log().take(), vec!["ident", "ident_len"]);
+}
+
+#[test]
+fn no_eq() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("foo".to_string());
+    assert_eq!(db.ratio_rounded(), 2);
+    assert_eq!(db.log().take(), vec!["ratio_rounded", "ratio"]);
+
+    // `ratio` is considered changed, even though its value is the
+    // same.
+    db.set_text("bar".to_string());
+    assert_eq!(db.ratio_rounded(), 2);
+    assert_eq!(db.log().take(), vec!["ratio", "ratio_rounded"]);
+}
@@ -0,0 +1,110 @@
+//! Test that `EventKind::WillExecute` explains why a query is
+//! executed.
+
+use crate::log::{HasLog, Log};
+use salsa::{Database, EventKind};
+
+#[salsa::query_group(ReasonStorage)]
+trait ReasonDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn sum(&self) -> u32;
+
+    #[salsa::dependencies]
+    fn no_value(&self) -> u32;
+
+    #[salsa::volatile]
+    fn volatile(&self) -> u32;
+}
+
+fn sum(db: &impl ReasonDatabase) -> u32 {
+    db.input(1) + db.input(2)
+}
+
+fn no_value(db: &impl ReasonDatabase) -> u32 {
+    db.input(1)
+}
+
+fn volatile(_db: &impl ReasonDatabase) -> u32 {
+    0
+}
+
+#[salsa::database(ReasonStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+
+    fn salsa_event(&self, event_fn: impl Fn() -> salsa::Event<Self>) {
+        if let EventKind::WillExecute {
+            database_key,
+            reason,
+        } = event_fn().kind
+        {
+            self.log.add(format!("{:?}: {:?}", database_key, reason));
+        }
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn input_changed() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    db.set_input(2, 20);
+    assert_eq!(db.sum(), 30);
+    assert_eq!(
+        db.log().take(),
+        vec!["__SalsaDatabaseKey { kind: ReasonStorage(sum(())) }: NotMemoized"]
+    );
+
+    db.set_input(2, 22);
+    assert_eq!(db.sum(), 32);
+    assert_eq!(
+        db.log().take(),
+        vec!["__SalsaDatabaseKey { kind: ReasonStorage(sum(())) }: InputChanged { database_key: __SalsaDatabaseKey { kind: ReasonStorage(input(2)) } }"]
+    );
+}
+
+#[test]
+fn no_memoized_value() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert_eq!(db.no_value(), 10);
+    db.set_input(2, 20);
+    assert_eq!(db.no_value(), 10);
+    assert_eq!(
+        db.log().take(),
+        vec![
+            "__SalsaDatabaseKey { kind: ReasonStorage(no_value(())) }: NotMemoized",
+            "__SalsaDatabaseKey { kind: ReasonStorage(no_value(())) }: NoMemoizedValue"
+        ]
+    );
+}
+
+#[test]
+fn untracked_inputs() {
+    let mut db = DatabaseImpl::default();
+    assert_eq!(db.volatile(), 0);
+    db.set_input(1, 10);
+    assert_eq!(db.volatile(), 0);
+    assert_eq!(
+        db.log().take(),
+        vec![
+            "__SalsaDatabaseKey { kind: ReasonStorage(volatile(())) }: NotMemoized",
+            "__SalsaDatabaseKey { kind: ReasonStorage(volatile(())) }: UntrackedInputs"
+        ]
+    );
+}
@@ -0,0 +1,135 @@
+//! Test that `#[salsa::lru]` and `set_lru_capacity` bound the number
+//! of memoized values.
+
+use crate::counter::{Counter, HasCounter};
+use salsa::Database;
+
+#[salsa::query_group(LruStorage)]
+trait LruDatabase: salsa::Database + HasCounter {
+    #[salsa::input]
+    fn input(&self, x: u32) -> u32;
+
+    #[salsa::lru(4)]
+    fn bounded(&self, x: u32) -> u32;
+
+    fn unbounded(&self, x: u32) -> u32;
+
+    fn outer(&self, x: u32) -> u32;
+}
+
+fn bounded(db: &impl LruDatabase, x: u32) -> u32 {
+    db.counter().increment();
+    db.input(x) * 2
+}
+
+fn unbounded(db: &impl LruDatabase, x: u32) -> u32 {
+    db.counter().increment();
+    db.input(x) * 3
+}
+
+fn outer(db: &impl LruDatabase, x: u32) -> u32 {
+    db.counter().increment();
+    db.bounded(x) + 1
+}
+
+#[salsa::database(LruStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    counter: Counter,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasCounter for DatabaseImpl {
+    fn counter(&self) -> &Counter {
+        &self.counter
+    }
+}
+
+impl DatabaseImpl {
+    fn with_inputs(n: u32) -> Self {
+        let mut db = DatabaseImpl::default();
+        for x in 0..n {
+            db.set_input(x, x);
+        }
+        db
+    }
+}
+
+#[test]
+fn lru_attribute() {
+    let db = DatabaseImpl::with_inputs(8);
+
+    for x in 0..8 {
+        assert_eq!(db.bounded(x), x * 2);
+    }
+    assert_eq!(db.counter().take(), 8);
+
+    // The four most recently used values are retained...
+    for x in 4..8 {
+        assert_eq!(db.bounded(x), x * 2);
+    }
+    assert_eq!(db.counter().take(), 0);
+
+    // ...but the others have to be recomputed.
+    for x in 0..4 {
+        assert_eq!(db.bounded(x), x * 2);
+    }
+    assert_eq!(db.counter().take(), 4);
+}
+
+#[test]
+fn set_lru_capacity() {
+    let db = DatabaseImpl::with_inputs(8);
+
+    db.query(UnboundedQuery).set_lru_capacity(4);
+    for x in 0..8 {
+        assert_eq!(db.unbounded(x), x * 3);
+    }
+    assert_eq!(db.counter().take(), 8);
+
+    // Shrinking the capacity evicts the least recently used values.
+    db.query(UnboundedQuery).set_lru_capacity(2);
+    for x in (0..8).rev() {
+        assert_eq!(db.unbounded(x), x * 3);
+    }
+    assert_eq!(db.counter().take(), 6);
+
+    // A capacity of zero removes the bound again.
+    db.query(UnboundedQuery).set_lru_capacity(0);
+    for x in 0..8 {
+        assert_eq!(db.unbounded(x), x * 3);
+    }
+    db.counter().take();
+    for x in 0..8 {
+        assert_eq!(db.unbounded(x), x * 3);
+    }
+    assert_eq!(db.counter().take(), 0);
+}
+
+#[test]
+fn evicted_values_keep_dependencies() {
+    let mut db = DatabaseImpl::with_inputs(8);
+
+    assert_eq!(db.outer(0), 1);
+    for x in 1..8 {
+        assert_eq!(db.bounded(x), x * 2);
+    }
+    db.counter().take();
+
+    // `bounded(0)` was evicted, but its inputs were kept: since they
+    // did not change, `outer(0)` is still up to date.
+    db.set_input(7, 70);
+    assert_eq!(db.outer(0), 1);
+    assert_eq!(db.counter().take(), 0);
+
+    // Once an input of the evicted value changes, both are recomputed.
+    db.set_input(0, 10);
+    assert_eq!(db.outer(0), 21);
+    assert_eq!(db.counter().take(), 2);
+}
@@ -0,0 +1,12 @@
+mod counter;
+mod durability;
+mod eq_with;
+mod execute_reason;
+mod log;
+mod lru;
+mod memoize_if;
+#[cfg(feature = "serde")]
+mod persist;
+mod remove_input;
+mod set_if_changed;
+mod transaction;
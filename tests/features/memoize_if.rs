@@ -0,0 +1,85 @@
+//! Test that `#[salsa::memoize_if]` memoizes the values of some keys
+//! only.
+
+use crate::log::{HasLog, Log};
+use salsa::Database;
+
+#[salsa::query_group(FilesStorage)]
+trait FilesDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn file_text(&self, name: &'static str) -> String;
+
+    #[salsa::memoize_if(is_open)]
+    fn file_len(&self, name: &'static str) -> usize;
+
+    fn total_len(&self) -> usize;
+}
+
+fn is_open(name: &&'static str) -> bool {
+    name.starts_with("open/")
+}
+
+fn file_len(db: &impl FilesDatabase, name: &'static str) -> usize {
+    db.log().add(format!("file_len({})", name));
+    db.file_text(name).len()
+}
+
+fn total_len(db: &impl FilesDatabase) -> usize {
+    db.log().add("total_len");
+    db.file_len("open/a") + db.file_len("lib/b")
+}
+
+#[salsa::database(FilesStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn memoize_open_files() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text("open/a", "hello".to_string());
+    db.set_file_text("lib/b", "hi".to_string());
+
+    assert_eq!(db.file_len("open/a"), 5);
+    assert_eq!(db.file_len("lib/b"), 2);
+    assert_eq!(db.log().take(), vec!["file_len(open/a)", "file_len(lib/b)"]);
+
+    // Only the value of the open file was memoized.
+    assert_eq!(db.file_len("open/a"), 5);
+    assert_eq!(db.file_len("lib/b"), 2);
+    assert_eq!(db.log().take(), vec!["file_len(lib/b)"]);
+}
+
+#[test]
+fn track_dependencies() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text("open/a", "hello".to_string());
+    db.set_file_text("lib/b", "hi".to_string());
+    assert_eq!(db.total_len(), 7);
+    db.log().take();
+
+    // The dependencies of the library file are still tracked, so an
+    // unrelated change does not re-execute anything.
+    db.set_file_text("lib/c", "hey".to_string());
+    assert_eq!(db.total_len(), 7);
+    assert_eq!(db.log().take(), Vec::<String>::new());
+
+    // The memoized value of the open file is reused.
+    db.set_file_text("lib/b", "hey".to_string());
+    assert_eq!(db.total_len(), 8);
+    assert_eq!(db.log().take(), vec!["total_len", "file_len(lib/b)"]);
+}
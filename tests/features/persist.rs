@@ -0,0 +1,92 @@
+//! Test that a database saved with `save_to` can be restored with
+//! `load_from` without re-executing its queries.
+
+use crate::counter::{Counter, HasCounter};
+use salsa::Database;
+
+#[salsa::query_group(PersistStorage)]
+trait PersistDatabase: salsa::Database + HasCounter {
+    #[salsa::input]
+    fn input(&self, x: u32) -> u32;
+
+    fn double(&self, x: u32) -> u32;
+
+    fn sum(&self, x: u32) -> u32;
+
+    #[salsa::interned]
+    fn intern_name(&self, x: String) -> salsa::InternId;
+}
+
+fn double(db: &impl PersistDatabase, x: u32) -> u32 {
+    db.counter().increment();
+    db.input(x) * 2
+}
+
+fn sum(db: &impl PersistDatabase, x: u32) -> u32 {
+    db.counter().increment();
+    (0..=x).map(|x| db.double(x)).sum()
+}
+
+#[salsa::database(PersistStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    counter: Counter,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasCounter for DatabaseImpl {
+    fn counter(&self) -> &Counter {
+        &self.counter
+    }
+}
+
+fn saved_database() -> Vec<u8> {
+    let mut db = DatabaseImpl::default();
+    for x in 0..4 {
+        db.set_input(x, x);
+    }
+    db.set_input(0, 10);
+    db.intern_name("foo".to_string());
+    assert_eq!(db.sum(3), 32);
+    assert_eq!(db.counter().take(), 5);
+
+    let mut bytes = vec![];
+    db.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn load_without_reexecuting() {
+    let mut db = DatabaseImpl::default();
+    db.load_from(&saved_database()[..]).unwrap();
+
+    assert_eq!(db.sum(3), 32);
+    assert_eq!(db.counter().take(), 0);
+    assert_eq!(
+        db.lookup_intern_name(db.intern_name("foo".to_string())),
+        "foo"
+    );
+}
+
+#[test]
+fn load_then_change_input() {
+    let mut db = DatabaseImpl::default();
+    db.load_from(&saved_database()[..]).unwrap();
+
+    db.set_input(2, 5);
+    assert_eq!(db.sum(3), 38);
+    assert_eq!(db.counter().take(), 2);
+}
+
+#[test]
+fn load_into_used_database() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0, 0);
+    assert!(db.load_from(&saved_database()[..]).is_err());
+}
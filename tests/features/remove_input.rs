@@ -0,0 +1,104 @@
+//! Test that input values can be removed, and that derived queries
+//! can observe their absence through `get_if_set`.
+
+use crate::counter::{Counter, HasCounter};
+use salsa::Database;
+
+#[salsa::query_group(FilesStorage)]
+trait FilesDatabase: salsa::Database + HasCounter {
+    #[salsa::input]
+    fn file_text(&self, name: &'static str) -> String;
+
+    fn file_len(&self, name: &'static str) -> Option<usize>;
+}
+
+fn file_len(db: &impl FilesDatabase, name: &'static str) -> Option<usize> {
+    db.counter().increment();
+    db.file_text_if_set(name).map(|text| text.len())
+}
+
+#[salsa::database(FilesStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    counter: Counter,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasCounter for DatabaseImpl {
+    fn counter(&self) -> &Counter {
+        &self.counter
+    }
+}
+
+#[test]
+fn get_if_set() {
+    let mut db = DatabaseImpl::default();
+    assert_eq!(db.query(FileTextQuery).get_if_set("a"), None);
+
+    db.set_file_text("a", "hello".to_string());
+    assert_eq!(db.file_text_if_set("a"), Some("hello".to_string()));
+}
+
+#[test]
+fn remove_invalidates_dependents() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text("a", "hello".to_string());
+    db.set_file_text("b", "hi".to_string());
+    assert_eq!(db.file_len("a"), Some(5));
+    assert_eq!(db.file_len("b"), Some(2));
+    assert_eq!(db.counter().take(), 2);
+
+    db.remove_file_text("a");
+    assert_eq!(db.file_len("a"), None);
+    assert_eq!(db.file_len("b"), Some(2));
+    assert_eq!(db.counter().take(), 1);
+
+    // The absence is tracked like a value...
+    db.set_file_text("b", "hey".to_string());
+    assert_eq!(db.file_len("a"), None);
+    assert_eq!(db.counter().take(), 0);
+
+    // ...so setting the key again invalidates it.
+    db.set_file_text("a", "bye".to_string());
+    assert_eq!(db.file_len("a"), Some(3));
+    assert_eq!(db.counter().take(), 1);
+}
+
+#[test]
+fn remove_through_query_table() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text("a", "hello".to_string());
+    db.query_mut(FileTextQuery).remove("a");
+    assert_eq!(db.file_text_if_set("a"), None);
+}
+
+#[test]
+fn keys_that_were_never_set() {
+    let mut db = DatabaseImpl::default();
+    assert_eq!(db.file_len("a"), None);
+    db.counter().take();
+
+    // Removing a key without a value does not start a new revision.
+    db.remove_file_text("a");
+    assert_eq!(db.file_len("a"), None);
+    assert_eq!(db.counter().take(), 0);
+
+    db.set_file_text("a", "hello".to_string());
+    assert_eq!(db.file_len("a"), Some(5));
+    assert_eq!(db.counter().take(), 1);
+}
+
+#[test]
+#[should_panic(expected = "no value set for FileTextQuery(\"a\")")]
+fn get_removed_key() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text("a", "hello".to_string());
+    db.remove_file_text("a");
+    db.file_text("a");
+}
@@ -0,0 +1,134 @@
+//! Test that `set_if_changed` leaves inputs set to an equal value
+//! untouched.
+
+use crate::counter::{Counter, HasCounter};
+use salsa::Database;
+
+#[salsa::query_group(FilesStorage)]
+trait FilesDatabase: salsa::Database + HasCounter {
+    #[salsa::input]
+    fn file_text(&self, name: &'static str) -> String;
+
+    fn file_len(&self, name: &'static str) -> usize;
+}
+
+fn file_len(db: &impl FilesDatabase, name: &'static str) -> usize {
+    db.counter().increment();
+    db.file_text(name).len()
+}
+
+#[salsa::database(FilesStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    counter: Counter,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasCounter for DatabaseImpl {
+    fn counter(&self) -> &Counter {
+        &self.counter
+    }
+}
+
+#[test]
+fn equal_value() {
+    let mut db = DatabaseImpl::default();
+    db.query_mut(FileTextQuery)
+        .set_if_changed("a", "hello".to_string());
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.counter().take(), 1);
+
+    db.query_mut(FileTextQuery)
+        .set_if_changed("a", "hello".to_string());
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.counter().take(), 0);
+
+    // Unlike `set_if_changed`, `set` invalidates dependents even if
+    // the value is equal.
+    db.set_file_text("a", "hello".to_string());
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.counter().take(), 1);
+}
+
+#[test]
+fn changed_value() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text("a", "hello".to_string());
+    assert_eq!(db.file_len("a"), 5);
+    db.counter().take();
+
+    db.query_mut(FileTextQuery)
+        .set_if_changed("a", "hi".to_string());
+    assert_eq!(db.file_len("a"), 2);
+    assert_eq!(db.counter().take(), 1);
+}
+
+#[test]
+fn changed_durability() {
+    let mut db = DatabaseImpl::default();
+    db.query_mut(FileTextQuery).set_with_durability(
+        "a",
+        "hello".to_string(),
+        salsa::Durability::High,
+    );
+    assert_eq!(db.file_len("a"), 5);
+    db.counter().take();
+
+    db.query_mut(FileTextQuery)
+        .set_if_changed("a", "hello".to_string());
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.counter().take(), 1);
+}
+
+#[test]
+fn equal_value_with_durability() {
+    let mut db = DatabaseImpl::default();
+    db.query_mut(FileTextQuery).set_with_durability(
+        "a",
+        "hello".to_string(),
+        salsa::Durability::High,
+    );
+    assert_eq!(db.file_len("a"), 5);
+    db.counter().take();
+
+    db.query_mut(FileTextQuery).set_if_changed_with_durability(
+        "a",
+        "hello".to_string(),
+        salsa::Durability::High,
+    );
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.counter().take(), 0);
+
+    // An equal value with another durability is a change
+    db.query_mut(FileTextQuery).set_if_changed_with_durability(
+        "a",
+        "hello".to_string(),
+        salsa::Durability::Medium,
+    );
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.counter().take(), 1);
+}
+
+#[test]
+fn in_transaction() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text("a", "hello".to_string());
+    db.set_file_text("b", "hi".to_string());
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.file_len("b"), 2);
+    db.counter().take();
+
+    db.transaction(|tx| {
+        tx.set_if_changed::<FileTextQuery>("a", "hello".to_string());
+        tx.set_if_changed::<FileTextQuery>("b", "hey".to_string());
+    });
+    assert_eq!(db.file_len("a"), 5);
+    assert_eq!(db.file_len("b"), 3);
+    assert_eq!(db.counter().take(), 1);
+}
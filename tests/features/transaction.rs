@@ -0,0 +1,202 @@
+//! Test that `Database::transaction` applies all of its changes in a
+//! single revision.
+
+use crate::log::{HasLog, Log};
+use salsa::{Database, Durability, EventKind};
+
+#[salsa::query_group(TransactionStorage)]
+trait TransactionDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn sum(&self) -> u32;
+
+    fn first(&self) -> u32;
+}
+
+fn sum(db: &impl TransactionDatabase) -> u32 {
+    db.input(1) + db.input(2) + db.input(3)
+}
+
+fn first(db: &impl TransactionDatabase) -> u32 {
+    db.input(1)
+}
+
+#[salsa::database(TransactionStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+
+    fn salsa_event(&self, event_fn: impl Fn() -> salsa::Event<Self>) {
+        match event_fn().kind {
+            EventKind::WillChangeInputValue { database_key } => {
+                self.log.add(format!("change {:?}", database_key))
+            }
+            EventKind::WillExecute { database_key, .. } => {
+                self.log.add(format!("execute {:?}", database_key))
+            }
+            EventKind::DidValidateMemoizedValue { database_key } => {
+                self.log.add(format!("validate {:?}", database_key))
+            }
+            _ => {}
+        }
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+impl DatabaseImpl {
+    /// The labels of the dependency graph nodes that describe inputs.
+    fn input_revisions(&self) -> Vec<String> {
+        let mut labels: Vec<String> = salsa::debug::dependency_graph(self)
+            .lines()
+            .filter_map(|line| line.split("[label=\"").nth(1))
+            .filter(|label| label.starts_with("InputQuery"))
+            .map(|label| label.trim_end_matches("\"];").to_string())
+            .collect();
+        labels.sort();
+        labels
+    }
+}
+
+#[test]
+fn single_revision() {
+    let mut db = DatabaseImpl::default();
+    db.transaction(|tx| {
+        tx.set::<InputQuery>(1, 1);
+        tx.set::<InputQuery>(2, 2);
+        tx.set::<InputQuery>(3, 3);
+    });
+    assert_eq!(db.sum(), 6);
+    assert_eq!(
+        db.log().take(),
+        vec![
+            "change __SalsaDatabaseKey { kind: TransactionStorage(input(1)) }",
+            "change __SalsaDatabaseKey { kind: TransactionStorage(input(2)) }",
+            "change __SalsaDatabaseKey { kind: TransactionStorage(input(3)) }",
+            "execute __SalsaDatabaseKey { kind: TransactionStorage(sum(())) }",
+        ]
+    );
+    assert_eq!(
+        db.input_revisions(),
+        vec![
+            r#"InputQuery(1)\nchanged_at: R1"#,
+            r#"InputQuery(2)\nchanged_at: R1"#,
+            r#"InputQuery(3)\nchanged_at: R1"#,
+        ]
+    );
+
+    db.transaction(|tx| {
+        tx.set::<InputQuery>(1, 10);
+        tx.remove::<InputQuery>(3);
+        tx.set::<InputQuery>(3, 30);
+    });
+    assert_eq!(db.sum(), 42);
+    assert_eq!(
+        db.log().take(),
+        vec![
+            "change __SalsaDatabaseKey { kind: TransactionStorage(input(1)) }",
+            "change __SalsaDatabaseKey { kind: TransactionStorage(input(3)) }",
+            "change __SalsaDatabaseKey { kind: TransactionStorage(input(3)) }",
+            "execute __SalsaDatabaseKey { kind: TransactionStorage(sum(())) }",
+        ]
+    );
+    assert_eq!(
+        db.input_revisions(),
+        vec![
+            r#"InputQuery(1)\nchanged_at: R2"#,
+            r#"InputQuery(2)\nchanged_at: R1"#,
+            r#"InputQuery(3)\nchanged_at: R2"#,
+        ]
+    );
+}
+
+#[test]
+fn unchanged_transaction() {
+    let mut db = DatabaseImpl::default();
+    db.transaction(|tx| {
+        tx.set::<InputQuery>(1, 1);
+        tx.set::<InputQuery>(2, 2);
+        tx.set::<InputQuery>(3, 3);
+    });
+    assert_eq!(db.sum(), 6);
+    db.log().take();
+
+    // No input changes, so no new revision is started, and `sum` does
+    // not even need to be validated.
+    db.transaction(|tx| {
+        tx.set_if_changed::<InputQuery>(1, 1);
+        tx.set_if_changed::<InputQuery>(2, 2);
+    });
+    assert_eq!(db.sum(), 6);
+    assert_eq!(db.log().take(), Vec::<String>::new());
+
+    db.transaction(|tx| {
+        tx.set_if_changed::<InputQuery>(1, 1);
+        tx.set_if_changed::<InputQuery>(2, 20);
+    });
+    assert_eq!(db.sum(), 24);
+    assert_eq!(
+        db.log().take(),
+        vec![
+            "change __SalsaDatabaseKey { kind: TransactionStorage(input(2)) }",
+            "execute __SalsaDatabaseKey { kind: TransactionStorage(sum(())) }",
+        ]
+    );
+    assert_eq!(
+        db.input_revisions(),
+        vec![
+            r#"InputQuery(1)\nchanged_at: R1"#,
+            r#"InputQuery(2)\nchanged_at: R2"#,
+            r#"InputQuery(3)\nchanged_at: R1"#,
+        ]
+    );
+}
+
+#[test]
+fn durability() {
+    let mut db = DatabaseImpl::default();
+    db.query_mut(InputQuery)
+        .set_with_durability(1, 1, Durability::High);
+    assert_eq!(db.first(), 1);
+
+    // The change to the high durability input must be recorded as
+    // such, even though the transaction also changes a low durability
+    // one afterwards.
+    db.transaction(|tx| {
+        tx.set_with_durability::<InputQuery>(1, 10, Durability::High);
+        tx.set::<InputQuery>(2, 2);
+    });
+    assert_eq!(db.first(), 10);
+}
+
+#[test]
+fn panicking_transaction() {
+    let mut db = DatabaseImpl::default();
+    db.query_mut(InputQuery)
+        .set_with_durability(1, 1, Durability::High);
+    assert_eq!(db.first(), 1);
+
+    // The input was changed before the transaction panicked, so the
+    // query that reads it must not be reused on account of its
+    // durability.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        db.transaction(|tx| {
+            tx.set_with_durability::<InputQuery>(1, 10, Durability::High);
+            panic!("transaction failed");
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(db.first(), 10);
+}
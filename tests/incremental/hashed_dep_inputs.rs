@@ -0,0 +1,57 @@
+use crate::implementation::{TestContext, TestContextImpl};
+
+#[salsa::query_group(HashedDepInputs)]
+pub(crate) trait HashedDepInputsContext: TestContext {
+    fn hashed_memoized(&self) -> usize;
+    #[salsa::hashed]
+    fn hashed_derived(&self) -> usize;
+    #[salsa::input]
+    fn hashed_input(&self) -> usize;
+    #[salsa::input]
+    fn hashed_unrelated_input(&self) -> usize;
+}
+
+fn hashed_memoized(db: &impl HashedDepInputsContext) -> usize {
+    db.log().add("Memoized invoked");
+    db.hashed_derived() * 2
+}
+
+fn hashed_derived(db: &impl HashedDepInputsContext) -> usize {
+    db.log().add("Derived invoked");
+    db.hashed_input() / 2
+}
+
+#[test]
+fn early_cutoff() {
+    let db = &mut TestContextImpl::default();
+
+    db.set_hashed_input(44);
+    assert_eq!(db.hashed_memoized(), 44);
+    db.assert_log(&["Memoized invoked", "Derived invoked"]);
+
+    // The value of Derived is not memoized, so it is re-executed
+    // whenever Memoized is.
+    db.set_hashed_input(45);
+    assert_eq!(db.hashed_memoized(), 44);
+    db.assert_log(&["Derived invoked"]);
+
+    db.set_hashed_input(46);
+    assert_eq!(db.hashed_memoized(), 46);
+    db.assert_log(&["Derived invoked", "Memoized invoked", "Derived invoked"]);
+}
+
+#[test]
+fn unrelated_input_changed() {
+    let db = &mut TestContextImpl::default();
+
+    db.set_hashed_input(44);
+    db.set_hashed_unrelated_input(0);
+    assert_eq!(db.hashed_memoized(), 44);
+    db.assert_log(&["Memoized invoked", "Derived invoked"]);
+
+    // None of the inputs of Derived changed, so it is not re-executed
+    // to compare the hash of its value.
+    db.set_hashed_unrelated_input(1);
+    assert_eq!(db.hashed_memoized(), 44);
+    db.assert_log(&[]);
+}
@@ -1,5 +1,6 @@
 use crate::constants;
 use crate::counter::Counter;
+use crate::hashed_dep_inputs;
 use crate::log::Log;
 use crate::memoized_dep_inputs;
 use crate::memoized_inputs;
@@ -12,6 +13,7 @@ pub(crate) trait TestContext: salsa::Database {
 
 #[salsa::database(
     constants::Constants,
+    hashed_dep_inputs::HashedDepInputs,
     memoized_dep_inputs::MemoizedDepInputs,
     memoized_inputs::MemoizedInputs,
     memoized_volatile::MemoizedVolatile
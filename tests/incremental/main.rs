@@ -1,5 +1,6 @@
 mod constants;
 mod counter;
+mod hashed_dep_inputs;
 mod implementation;
 mod log;
 mod memoized_dep_inputs;
@@ -0,0 +1,98 @@
+//! Test that `Database::memory_report` accounts for the entries of each
+//! query.
+
+use salsa::{Database, QueryMemoryUsage};
+use std::mem::size_of;
+
+#[salsa::query_group(MemoryStorage)]
+trait MemoryDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::heap_size]
+    fn text(&self, key: u32) -> String;
+
+    fn len(&self, key: u32) -> usize;
+
+    #[salsa::heap_size]
+    fn lens(&self, key: u32) -> Vec<usize>;
+}
+
+fn len(db: &impl MemoryDatabase, key: u32) -> usize {
+    db.text(key).len()
+}
+
+fn lens(db: &impl MemoryDatabase, key: u32) -> Vec<usize> {
+    vec![db.len(key); 2]
+}
+
+#[salsa::database(MemoryStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn usage(report: &[QueryMemoryUsage], query: &str) -> QueryMemoryUsage {
+    report
+        .iter()
+        .find(|usage| usage.query == query)
+        .unwrap()
+        .clone()
+}
+
+#[test]
+fn memory_report() {
+    let mut db = DatabaseImpl::default();
+    db.set_text(1, "hello".to_string());
+    db.set_text(2, "hi".to_string());
+    assert_eq!(db.lens(1), vec![5, 5]);
+
+    let report = db.memory_report();
+    assert_eq!(
+        usage(&report, "TextQuery"),
+        QueryMemoryUsage {
+            query: "TextQuery".to_string(),
+            entries: 2,
+            key_bytes: 2 * size_of::<u32>(),
+            value_bytes: 2 * size_of::<String>() + 5 + 2,
+            inputs_bytes: 0,
+        }
+    );
+
+    // Without `#[salsa::heap_size]`, only the `size_of` of keys and
+    // values is counted.
+    let len = usage(&report, "LenQuery");
+    assert_eq!(len.entries, 1);
+    assert_eq!(len.key_bytes, size_of::<u32>());
+    assert_eq!(len.value_bytes, size_of::<usize>());
+    assert!(len.inputs_bytes > 0);
+
+    let lens = usage(&report, "LensQuery");
+    assert_eq!(lens.entries, 1);
+    assert_eq!(lens.key_bytes, size_of::<u32>());
+    assert_eq!(
+        lens.value_bytes,
+        size_of::<Vec<usize>>() + 2 * size_of::<usize>()
+    );
+    assert!(lens.inputs_bytes > 0);
+}
+
+#[test]
+fn sweep() {
+    let mut db = DatabaseImpl::default();
+    db.set_text(1, "hello".to_string());
+    db.len(1);
+
+    db.sweep_all(
+        salsa::SweepStrategy::default()
+            .discard_values()
+            .sweep_all_revisions(),
+    );
+    let len = usage(&db.memory_report(), "LenQuery");
+    assert_eq!(len.entries, 1);
+    assert_eq!(len.value_bytes, 0);
+}
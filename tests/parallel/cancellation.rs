@@ -1,7 +1,5 @@
-use crate::setup::{
-    CancelationFlag, Canceled, Knobs, ParDatabase, ParDatabaseImpl, WithValue,
-};
-use salsa::ParallelDatabase;
+use crate::setup::{CancelationFlag, Knobs, ParDatabase, ParDatabaseImpl, WithValue};
+use salsa::{Canceled, Database, ParallelDatabase};
 
 macro_rules! assert_canceled {
     ($flag:expr, $thread:expr) => {
@@ -187,3 +185,67 @@ fn transitive_cancellation() {
 
     assert_eq!(thread1.join().unwrap(), 22);
 }
+
+/// Test that `catch_canceled` turns the unwinding of a canceled query
+/// into an `Err(Canceled)`, and that the canceled result is not
+/// memoized.
+#[test]
+fn catch_canceled() {
+    let mut db = ParDatabaseImpl::default();
+
+    db.set_input('a', 1);
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.catch_canceled(|db| {
+                db.knobs().sum_signal_on_entry.with_value(1, || {
+                    db.knobs()
+                        .sum_wait_for_cancellation
+                        .with_value(CancelationFlag::Panic, || db.sum2("a"))
+                })
+            })
+        }
+    });
+
+    db.wait_for(1);
+
+    db.set_input('b', 2);
+    assert_eq!(thread1.join().unwrap(), Err(Canceled));
+
+    let snapshot = db.snapshot();
+    assert_eq!(snapshot.catch_canceled(|db| db.sum2("a")), Ok(1));
+}
+
+/// Test that, with automatic cancellation, a query that is invoked
+/// once the revision is canceled unwinds with `Canceled`, even though
+/// it does not check for cancellation itself.
+#[test]
+fn automatic_cancellation() {
+    let mut db = ParDatabaseImpl::default();
+    db.salsa_runtime().set_automatic_cancellation(true);
+
+    db.set_input('a', 1);
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.catch_canceled(|db| {
+                let sum = db.knobs().sum_signal_on_entry.with_value(1, || {
+                    db.knobs()
+                        .sum_wait_for_cancellation
+                        .with_value(CancelationFlag::SpecialValue, || db.sum("a"))
+                });
+                assert_eq!(sum, usize::MAX);
+
+                // Without automatic cancellation, this would return the
+                // (memoized) special value.
+                db.sum2("a")
+            })
+        }
+    });
+
+    db.wait_for(1);
+
+    db.set_input('b', 2);
+    assert_eq!(thread1.join().unwrap(), Err(Canceled));
+    assert_eq!(db.sum2("a"), 1);
+}
@@ -0,0 +1,153 @@
+use salsa::{Database, ParallelDatabase, Snapshot};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Barrier};
+
+/// Each of the queries below waits on `barrier` when it starts
+/// executing, so that all three are in progress (on different
+/// threads) before any of them invokes the next one.
+#[salsa::query_group(CycleStorage)]
+trait CycleDatabase: salsa::Database + HasBarrier {
+    // `a1 -> b1 -> c1 -> a1` form a cycle without recovery
+    fn a1(&self) -> ();
+    fn b1(&self) -> ();
+    fn c1(&self) -> ();
+
+    // `a2 -> b2 -> c2 -> a2` form a cycle where each query can recover
+    #[salsa::cycle(recover)]
+    fn a2(&self) -> Vec<String>;
+    #[salsa::cycle(recover)]
+    fn b2(&self) -> Vec<String>;
+    #[salsa::cycle(recover)]
+    fn c2(&self) -> Vec<String>;
+}
+
+trait HasBarrier {
+    fn barrier(&self) -> &Barrier;
+}
+
+fn recover<DB: CycleDatabase>(_db: &DB, cycle: &salsa::Cycle<DB>) -> Vec<String> {
+    cycle
+        .participants()
+        .iter()
+        .map(|database_key| format!("{:?}", database_key))
+        .collect()
+}
+
+fn a1(db: &impl CycleDatabase) {
+    db.barrier().wait();
+    db.b1()
+}
+
+fn b1(db: &impl CycleDatabase) {
+    db.barrier().wait();
+    db.c1()
+}
+
+fn c1(db: &impl CycleDatabase) {
+    db.barrier().wait();
+    db.a1()
+}
+
+fn a2(db: &impl CycleDatabase) -> Vec<String> {
+    db.barrier().wait();
+    db.b2()
+}
+
+fn b2(db: &impl CycleDatabase) -> Vec<String> {
+    db.barrier().wait();
+    db.c2()
+}
+
+fn c2(db: &impl CycleDatabase) -> Vec<String> {
+    db.barrier().wait();
+    db.a2()
+}
+
+#[salsa::database(CycleStorage)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    barrier: Arc<Barrier>,
+}
+
+impl Default for DatabaseImpl {
+    fn default() -> Self {
+        DatabaseImpl {
+            runtime: Default::default(),
+            barrier: Arc::new(Barrier::new(3)),
+        }
+    }
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> Snapshot<Self> {
+        Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            barrier: self.barrier.clone(),
+        })
+    }
+}
+
+impl HasBarrier for DatabaseImpl {
+    fn barrier(&self) -> &Barrier {
+        &self.barrier
+    }
+}
+
+/// Runs `op` on a snapshot in a new thread, returning the panic
+/// message if it panics.
+fn spawn<T: Send + 'static>(
+    db: &DatabaseImpl,
+    op: impl FnOnce(&DatabaseImpl) -> T + Send + 'static,
+) -> std::thread::JoinHandle<Result<T, String>> {
+    let db = db.snapshot();
+    std::thread::spawn(move || {
+        panic::catch_unwind(AssertUnwindSafe(|| op(&db))).map_err(|payload| {
+            payload
+                .downcast::<String>()
+                .map(|message| *message)
+                .unwrap_or_default()
+        })
+    })
+}
+
+#[test]
+fn parallel_cycle_all_threads_panic() {
+    let db = DatabaseImpl::default();
+
+    let thread_a = spawn(&db, |db| db.a1());
+    let thread_b = spawn(&db, |db| db.b1());
+    let thread_c = spawn(&db, |db| db.c1());
+
+    let message_a = thread_a.join().unwrap().unwrap_err();
+    let message_b = thread_b.join().unwrap().unwrap_err();
+    let message_c = thread_c.join().unwrap().unwrap_err();
+
+    // Every thread reports the same cycle, whichever thread found it.
+    assert!(message_a.contains("cycle detected"), "{}", message_a);
+    assert_eq!(message_a.lines().count(), 4, "{}", message_a);
+    assert_eq!(message_a, message_b);
+    assert_eq!(message_a, message_c);
+}
+
+#[test]
+fn parallel_cycle_all_threads_recover() {
+    let db = DatabaseImpl::default();
+
+    let thread_a = spawn(&db, |db| db.a2());
+    let thread_b = spawn(&db, |db| db.b2());
+    let thread_c = spawn(&db, |db| db.c2());
+
+    let cycle_a = thread_a.join().unwrap().unwrap();
+    let cycle_b = thread_b.join().unwrap().unwrap();
+    let cycle_c = thread_c.join().unwrap().unwrap();
+
+    assert_eq!(cycle_a.len(), 3, "{:?}", cycle_a);
+    assert_eq!(cycle_a, cycle_b);
+    assert_eq!(cycle_a, cycle_c);
+}
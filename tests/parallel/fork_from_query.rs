@@ -1,4 +1,5 @@
-use crate::setup::{ParDatabase, ParDatabaseImpl};
+use crate::setup::{Knobs, ParDatabase, ParDatabaseImpl, WithValue};
+use salsa::ParallelDatabase;
 
 #[test]
 #[should_panic]
@@ -6,3 +7,86 @@ fn snapshot_from_query() {
     let db = ParDatabaseImpl::default();
     db.snapshot_me();
 }
+
+/// Test that the reads of queries invoked on forks are recorded as
+/// reads of the query that created them.
+#[test]
+fn fork_from_query() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    assert_eq!(db.sum_forked("ab"), 110);
+
+    db.set_input('a', 200);
+    assert_eq!(db.sum_forked("ab"), 210);
+
+    let dot = salsa::debug::dependency_graph(&db);
+    let sum_forked = node_id(&dot, r#"SumForkedQuery(\"ab\")"#);
+    for input in &["InputQuery('a')", "InputQuery('b')"] {
+        let edge = format!("    {} -> {};", sum_forked, node_id(&dot, input));
+        assert!(dot.lines().any(|line| line == edge));
+    }
+}
+
+/// Test that a cycle through a fork and another runtime is reported,
+/// rather than deadlocking: the query that created the fork waits for
+/// it, the fork is blocked on the other runtime, and the other runtime
+/// blocks on the query that created the fork.
+#[test]
+fn fork_cycle_through_other_runtime() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 1);
+
+    // Thread 1 will signal stage 1 when it enters `sum`, and wait for
+    // stage 2 before invoking `fork_cycle_inner`.
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().sum_signal_on_entry.with_value(1, || {
+                db.knobs()
+                    .sum_wait_for_on_entry
+                    .with_value(2, || db.fork_cycle_outer())
+            })
+        }
+    });
+
+    // Thread 2 will wait for stage 1, then invoke `fork_cycle_inner`,
+    // whose fork signals stage 2 when it blocks on thread 1.
+    let thread2 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.wait_for(1);
+            db.knobs()
+                .signal_on_will_block
+                .with_value(2, || db.fork_cycle_inner())
+        }
+    });
+
+    let payload = thread1.join().unwrap_err();
+    let message = payload.downcast_ref::<String>().unwrap();
+    assert!(message.contains("cycle detected"), "{}", message);
+    assert!(thread2.join().is_err());
+}
+
+/// Returns the identifier of the node of `dot` whose label starts with
+/// `label`.
+fn node_id<'dot>(dot: &'dot str, label: &str) -> &'dot str {
+    let prefix = format!(" [label=\"{}\\n", label);
+    dot.lines()
+        .find_map(|line| {
+            let index = line.find(&prefix)?;
+            Some(line[..index].trim())
+        })
+        .unwrap()
+}
+
+/// Test that a fork that requires the query that created it reports
+/// a cycle, rather than deadlocking.
+#[test]
+#[should_panic(expected = "cycle detected")]
+fn fork_cycle() {
+    let db = ParDatabaseImpl::default();
+    db.fork_me();
+}
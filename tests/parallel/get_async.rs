@@ -0,0 +1,121 @@
+use crate::setup::{Knobs, ParDatabase, ParDatabaseImpl, SumQuery, WithValue};
+use salsa::{Database, ParallelDatabase};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+/// Records whether it was woken.
+#[derive(Default)]
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn poll<F: Future + Unpin>(future: &mut F, waker: &Waker) -> Poll<F::Output> {
+    Pin::new(future).poll(&mut Context::from_waker(waker))
+}
+
+/// Test that `get_async` resolves right away if no other thread is
+/// computing the value.
+#[test]
+fn get_async_ready() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+
+    let mut future = db.query(SumQuery).get_async("ab");
+    fn assert_send<T: Send>(_: &T) {}
+    assert_send(&future);
+
+    let flag = Arc::new(Flag::default());
+    assert_eq!(poll(&mut future, &Waker::from(flag)), Poll::Ready(110));
+}
+
+/// Test that a future waiting on another thread yields, and is woken
+/// once the other thread is done.
+#[test]
+fn get_async_in_progress() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    // Thread 1 will signal stage 1 when it enters and wait for stage 2.
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().sum_signal_on_entry.with_value(1, || {
+                db.knobs()
+                    .sum_wait_for_on_exit
+                    .with_value(2, || db.sum("abc"))
+            })
+        }
+    });
+
+    db.wait_for(1);
+
+    let flag = Arc::new(Flag::default());
+    let waker = Waker::from(flag.clone());
+    let mut future = db.query(SumQuery).get_async("abc");
+    assert_eq!(poll(&mut future, &waker), Poll::Pending);
+    assert!(!flag.0.load(Ordering::SeqCst));
+
+    db.signal(2);
+    assert_eq!(thread1.join().unwrap(), 111);
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(poll(&mut future, &waker), Poll::Ready(111));
+}
+
+/// Test that polling a pending future again replaces its waker, rather
+/// than registering another one.
+#[test]
+fn get_async_polled_again() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+    db.salsa_runtime().set_query_stats_enabled(true);
+
+    // Thread 1 will signal stage 1 when it enters and wait for stage 2.
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().sum_signal_on_entry.with_value(1, || {
+                db.knobs()
+                    .sum_wait_for_on_exit
+                    .with_value(2, || db.sum("abc"))
+            })
+        }
+    });
+
+    db.wait_for(1);
+
+    let flag1 = Arc::new(Flag::default());
+    let flag2 = Arc::new(Flag::default());
+    let waker2 = Waker::from(flag2.clone());
+    let mut future = db.query(SumQuery).get_async("abc");
+    assert_eq!(
+        poll(&mut future, &Waker::from(flag1.clone())),
+        Poll::Pending
+    );
+    assert_eq!(poll(&mut future, &waker2), Poll::Pending);
+    assert_eq!(poll(&mut future, &waker2), Poll::Pending);
+
+    db.signal(2);
+    assert_eq!(thread1.join().unwrap(), 111);
+    assert!(!flag1.0.load(Ordering::SeqCst));
+    assert!(flag2.0.load(Ordering::SeqCst));
+    assert_eq!(poll(&mut future, &waker2), Poll::Ready(111));
+
+    let stats = db.query_stats();
+    let sum_stats = stats
+        .iter()
+        .find(|stats| stats.query == "SumQuery")
+        .unwrap();
+    assert_eq!(sum_stats.blocked, 1);
+}
@@ -0,0 +1,34 @@
+use crate::setup::ParDatabaseImpl;
+use salsa::{Database, ParallelDatabase};
+
+#[test]
+fn live_snapshots() {
+    let db = ParDatabaseImpl::default();
+    assert!(db.salsa_runtime().live_snapshots().is_empty());
+
+    let snapshot1 = db.snapshot();
+    snapshot1.salsa_runtime().set_snapshot_label("indexer");
+    db.salsa_runtime().set_snapshot_backtraces(true);
+    let snapshot2 = snapshot1.snapshot();
+
+    let snapshots = db.salsa_runtime().live_snapshots();
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[0].id, snapshot1.salsa_runtime().id());
+    assert_eq!(snapshots[0].label.as_ref().unwrap(), "indexer");
+    assert!(snapshots[0].backtrace.is_none());
+    assert_eq!(snapshots[1].id, snapshot2.salsa_runtime().id());
+    assert_eq!(snapshots[1].label, None);
+    assert!(snapshots[1].backtrace.is_some());
+
+    std::mem::drop(snapshot1);
+    let snapshots = db.salsa_runtime().live_snapshots();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].id, snapshot2.salsa_runtime().id());
+}
+
+#[test]
+#[should_panic(expected = "not a snapshot")]
+fn label_without_snapshot() {
+    let db = ParDatabaseImpl::default();
+    db.salsa_runtime().set_snapshot_label("main");
+}
@@ -1,10 +1,16 @@
 mod setup;
 
 mod cancellation;
+mod cycles;
 mod fork_from_query;
 mod frozen;
+mod get_async;
 mod independent;
+mod live_snapshots;
+mod par_map;
+mod priority;
 mod race;
 mod signal;
 mod stress;
 mod true_parallel;
+mod try_set;
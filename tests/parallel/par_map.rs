@@ -0,0 +1,40 @@
+use crate::setup::{ParDatabase, ParDatabaseImpl};
+use salsa::{Canceled, ParallelDatabase};
+use std::panic::{self, AssertUnwindSafe};
+
+#[test]
+fn par_map_in_order() {
+    let mut db = ParDatabaseImpl::default();
+    for (index, key) in "abcdefghij".chars().enumerate() {
+        db.set_input(key, index);
+    }
+
+    let results = db.par_map("abcdefghij".chars(), |db, key| db.input(key) * 2);
+    assert_eq!(results, vec![0, 2, 4, 6, 8, 10, 12, 14, 16, 18]);
+
+    let results = db.par_map(Vec::<char>::new(), |db, key| db.input(key));
+    assert!(results.is_empty());
+}
+
+#[test]
+fn par_map_propagate_panic() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 1);
+
+    // `ParDatabaseImpl::on_propagated_panic` unwinds with `Canceled`.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        db.par_map("aaaa".chars(), |db, key| {
+            if db.input(key) == 1 {
+                panic!("op panicked");
+            }
+        })
+    }));
+    assert!(result.unwrap_err().downcast::<Canceled>().is_ok());
+
+    // No snapshot is left behind.
+    db.set_input('a', 2);
+    assert_eq!(
+        db.par_map("aaaa".chars(), |db, key| db.input(key)),
+        vec![2; 4]
+    );
+}
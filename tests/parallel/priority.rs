@@ -0,0 +1,72 @@
+use crate::setup::{Knobs, ParDatabase, ParDatabaseImpl, WithValue};
+use salsa::{Canceled, Database, ParallelDatabase, Priority};
+
+/// Thread 1 (in the background) computes `sum` and waits for thread
+/// 2 to block on it; thread 1 then checks for cancellation.
+fn race(thread1_priority: Priority) -> (Result<usize, Canceled>, usize) {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        db.salsa_runtime().set_snapshot_priority(thread1_priority);
+        move || {
+            db.catch_canceled(|db| {
+                db.knobs().sum_signal_on_entry.with_value(1, || {
+                    db.knobs().sum_wait_for_on_exit.with_value(2, || {
+                        db.knobs()
+                            .sum_unwind_if_canceled_on_exit
+                            .with_value(true, || db.sum("abc"))
+                    })
+                })
+            })
+        }
+    });
+
+    db.wait_for(1);
+
+    let thread2 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs()
+                .signal_on_will_block
+                .with_value(2, || db.sum("abc"))
+        }
+    });
+
+    (thread1.join().unwrap(), thread2.join().unwrap())
+}
+
+/// Test that an interactive snapshot preempts a background one that
+/// computes the query it needs, and computes the query itself.
+#[test]
+fn preempt_background() {
+    assert_eq!(race(Priority::Background), (Err(Canceled), 111));
+}
+
+/// Test that snapshots of equal priority wait for one another.
+#[test]
+fn equal_priority() {
+    assert_eq!(race(Priority::Interactive), (Ok(111), 111));
+}
+
+#[test]
+fn live_snapshot_priority() {
+    let db = ParDatabaseImpl::default();
+    let snapshot = db.snapshot();
+    snapshot
+        .salsa_runtime()
+        .set_snapshot_priority(Priority::Background);
+    let background = snapshot.snapshot();
+
+    let priorities: Vec<Priority> = db
+        .salsa_runtime()
+        .live_snapshots()
+        .into_iter()
+        .map(|snapshot| snapshot.priority)
+        .collect();
+    assert_eq!(priorities, vec![Priority::Background, Priority::Background]);
+    drop(background);
+}
@@ -1,4 +1,5 @@
 use crate::signal::Signal;
+use salsa::Canceled;
 use salsa::Database;
 use salsa::ParallelDatabase;
 use salsa::Snapshot;
@@ -25,16 +26,18 @@ pub(crate) trait ParDatabase: Knobs + salsa::ParallelDatabase {
     fn sum3_drop_sum(&self, key: &'static str) -> usize;
 
     fn snapshot_me(&self) -> ();
-}
 
-#[derive(PartialEq, Eq)]
-pub(crate) struct Canceled;
+    /// Like `sum`, but reads each input on a separate fork.
+    fn sum_forked(&self, key: &'static str) -> usize;
 
-impl Canceled {
-    fn throw() -> ! {
-        // Don't print backtrace
-        std::panic::resume_unwind(Box::new(Canceled));
-    }
+    /// Invokes itself on a fork.
+    fn fork_me(&self) -> ();
+
+    /// Invokes `sum("a")`, then `fork_cycle_inner`.
+    fn fork_cycle_outer(&self) -> ();
+
+    /// Invokes `fork_cycle_outer` on a fork.
+    fn fork_cycle_inner(&self) -> ();
 }
 
 /// Various "knobs" and utilities used by tests to force
@@ -107,6 +110,10 @@ pub(crate) struct KnobsStruct {
     /// Invocations of `sum` will signal this stage prior to exiting.
     pub(crate) sum_signal_on_exit: Cell<usize>,
 
+    /// If true, invocations of `sum` will invoke `unwind_if_canceled`
+    /// after waiting for `sum_wait_for_on_exit`.
+    pub(crate) sum_unwind_if_canceled_on_exit: Cell<bool>,
+
     /// Invocations of `sum3_drop_sum` will panic unconditionally
     pub(crate) sum3_drop_sum_should_panic: Cell<bool>,
 }
@@ -135,7 +142,7 @@ fn sum(db: &impl ParDatabase, key: &'static str) -> usize {
             }
             log::debug!("observed cancelation");
             if flag == CancelationFlag::Panic {
-                Canceled::throw();
+                db.salsa_runtime().unwind_if_canceled();
             }
         }
     }
@@ -154,6 +161,10 @@ fn sum(db: &impl ParDatabase, key: &'static str) -> usize {
 
     db.wait_for(db.knobs().sum_wait_for_on_exit.get());
 
+    if db.knobs().sum_unwind_if_canceled_on_exit.get() {
+        db.salsa_runtime().unwind_if_canceled();
+    }
+
     db.signal(db.knobs().sum_signal_on_exit.get());
 
     sum
@@ -184,6 +195,47 @@ fn snapshot_me(db: &impl ParDatabase) {
     db.snapshot();
 }
 
+fn sum_forked(db: &impl ParDatabase, key: &'static str) -> usize {
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = key
+            .chars()
+            .map(|ch| {
+                let db = db.fork();
+                scope.spawn(move || db.input(ch))
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .sum()
+    })
+}
+
+fn fork_me(db: &impl ParDatabase) {
+    let db = db.fork();
+    std::thread::scope(|scope| {
+        scope
+            .spawn(move || db.fork_me())
+            .join()
+            .unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+    })
+}
+
+fn fork_cycle_outer(db: &impl ParDatabase) {
+    db.sum("a");
+    db.fork_cycle_inner()
+}
+
+fn fork_cycle_inner(db: &impl ParDatabase) {
+    let db = db.fork();
+    std::thread::scope(|scope| {
+        scope
+            .spawn(move || db.fork_cycle_outer())
+            .join()
+            .unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+    })
+}
+
 #[salsa::database(Par)]
 #[derive(Default)]
 pub(crate) struct ParDatabaseImpl {
@@ -0,0 +1,47 @@
+use crate::setup::{InputQuery, ParDatabase, ParDatabaseImpl};
+use salsa::{Database, ParallelDatabase};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Test that `try_set` gives up while a snapshot is alive, leaving the
+/// input (and the snapshot) untouched.
+#[test]
+fn try_set_with_snapshot() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 1);
+
+    let snapshot = db.snapshot();
+    let error = db
+        .query_mut(InputQuery)
+        .try_set('a', 2, TIMEOUT)
+        .unwrap_err();
+    assert_eq!(error.snapshots(), &[snapshot.salsa_runtime().id()]);
+    assert!(!snapshot.salsa_runtime().is_current_revision_canceled());
+    assert_eq!(snapshot.input('a'), 1);
+
+    std::mem::drop(snapshot);
+    db.query_mut(InputQuery).try_set('a', 2, TIMEOUT).unwrap();
+    assert_eq!(db.input('a'), 2);
+}
+
+/// Test that `try_next_revision` succeeds once the snapshot that
+/// blocks it is dropped by another thread.
+#[test]
+fn try_next_revision() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 1);
+
+    let snapshot = db.snapshot();
+    assert!(db.salsa_runtime().try_next_revision(TIMEOUT).is_err());
+
+    let thread1 = std::thread::spawn(move || {
+        while !snapshot.salsa_runtime().is_current_revision_canceled() {
+            std::thread::yield_now();
+        }
+    });
+    db.salsa_runtime()
+        .try_next_revision(Duration::from_secs(60))
+        .unwrap();
+    thread1.join().unwrap();
+}
@@ -0,0 +1,75 @@
+//! Test that `Database::query_stats` counts executions, hits and
+//! validations of each query.
+
+use salsa::{Database, QueryStats};
+
+#[salsa::query_group(StatsStorage)]
+trait StatsDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn double(&self, key: u32) -> u32;
+
+    fn sum(&self) -> u32;
+}
+
+fn double(db: &impl StatsDatabase, key: u32) -> u32 {
+    db.input(key) * 2
+}
+
+fn sum(db: &impl StatsDatabase) -> u32 {
+    db.double(1) + db.double(2)
+}
+
+#[salsa::database(StatsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+/// The stats of `query`, without the timing.
+fn counts(stats: &[QueryStats], query: &str) -> (u64, u64, u64) {
+    let stats = stats.iter().find(|stats| stats.query == query).unwrap();
+    (stats.executions, stats.hits, stats.validations)
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 1);
+    db.set_input(2, 2);
+    assert_eq!(db.sum(), 6);
+    assert_eq!(db.query_stats(), vec![]);
+}
+
+#[test]
+fn count_executions() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_query_stats_enabled(true);
+    db.set_input(1, 1);
+    db.set_input(2, 2);
+
+    assert_eq!(db.sum(), 6);
+    assert_eq!(db.sum(), 6);
+    let stats = db.query_stats();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(counts(&stats, "SumQuery"), (1, 1, 0));
+    assert_eq!(counts(&stats, "DoubleQuery"), (2, 0, 0));
+
+    // While checking whether `sum` is up to date, `double(1)` is
+    // validated and `double(2)` is re-executed, as its input changed.
+    // `sum` then finds both of them up to date, as does `double(1)`.
+    db.salsa_runtime().reset_query_stats();
+    db.set_input(2, 3);
+    assert_eq!(db.sum(), 8);
+    assert_eq!(db.double(1), 2);
+    let stats = db.query_stats();
+    assert_eq!(counts(&stats, "SumQuery"), (1, 0, 0));
+    assert_eq!(counts(&stats, "DoubleQuery"), (1, 3, 1));
+}